@@ -0,0 +1,428 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use quicklabel::{
+    Class, Dataset, Error,
+    collision::CollisionPolicy,
+    fsops::{self, TransferMode},
+    tokens,
+    xmp::XmpSink,
+};
+
+use crate::project::Project;
+
+/// A fast image labeling tool for creating text-to-image finetuning datasets.
+///
+/// Launches the GUI unless a command is given.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub launch: LaunchArgs,
+}
+
+/// Fills in the GUI, skipping ahead to the options once the directories are known, and straight
+/// into labeling once there are classes as well
+#[derive(Debug, Args)]
+pub struct LaunchArgs {
+    /// Directory with the images to label
+    #[arg(long, env = "QUICKLABEL_INPUT")]
+    pub input: Option<PathBuf>,
+    /// Root directory of the dataset
+    #[arg(long, env = "QUICKLABEL_OUTPUT")]
+    pub output: Option<PathBuf>,
+    /// Directory trashed images are moved to
+    #[arg(long, env = "QUICKLABEL_TRASH")]
+    pub trash: Option<PathBuf>,
+    /// Class to label into as repeats:label, e.g. 10:dog. Can be repeated, or comma separated in
+    /// the environment variable
+    #[arg(
+        long = "class",
+        value_name = "CLASS",
+        env = "QUICKLABEL_CLASSES",
+        value_delimiter = ',',
+        value_parser = parse_class,
+        requires_all = ["input", "output"]
+    )]
+    pub classes: Vec<Class>,
+    /// Prompt pre-filled for every image
+    #[arg(long, env = "QUICKLABEL_PREFILL", requires_all = ["input", "output"])]
+    pub prefill: Option<String>,
+    /// Project file to resume labeling from
+    #[arg(
+        long,
+        env = "QUICKLABEL_PROJECT",
+        conflicts_with_all = ["input", "output", "trash", "classes", "prefill"]
+    )]
+    pub project: Option<PathBuf>,
+}
+
+impl LaunchArgs {
+    /// Checks that the directories exist
+    pub fn validate(&self) -> Result<(), String> {
+        let directories = [
+            ("--input", &self.input),
+            ("--output", &self.output),
+            ("--trash", &self.trash),
+        ];
+
+        for (name, directory) in directories {
+            if let Some(directory) = directory
+                && !directory.is_dir()
+            {
+                return Err(format!("{name} {directory:?} isn't a directory"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Label images without the GUI, transferring them into a class directory with a caption
+    Label(LabelArgs),
+    /// Show how many images each class directory holds
+    Stats(DatasetArgs),
+    /// Check the dataset for missing captions and other problems
+    Validate(ValidateArgs),
+    /// Write a manifest listing every image with its class and caption
+    Export(ExportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct LabelArgs {
+    /// Directory with the images to label
+    #[arg(long)]
+    input: PathBuf,
+    /// Root directory of the dataset
+    #[arg(long)]
+    output: PathBuf,
+    /// Class directory to label into, e.g. 10_dog
+    #[arg(long, value_parser = parse_class)]
+    class: Class,
+    /// Trigger of the class, put in place of {trigger} in the caption or in front of it. Defaults
+    /// to the trigger the project has for the class
+    #[arg(long)]
+    trigger: Option<String>,
+    /// Caption written next to every image, into every caption file of the project
+    #[arg(long, conflicts_with = "caption_file")]
+    caption: Option<String>,
+    /// File containing the caption written next to every image
+    #[arg(long)]
+    caption_file: Option<PathBuf>,
+    /// move, copy, hardlink or symlink. Defaults to the setting of the project in the output
+    /// directory, or move
+    #[arg(long)]
+    transfer_mode: Option<TransferMode>,
+    /// suffix, hash, sequential, overwrite or ask. With ask, images whose name is taken are left
+    /// for labeling in the GUI
+    #[arg(long)]
+    collision: Option<CollisionPolicy>,
+    /// off, embed or sidecar. Writes the caption and class as XMP as well. Defaults to the setting
+    /// of the project
+    #[arg(long)]
+    xmp: Option<XmpSink>,
+    /// Images to label. Defaults to every image in the input directory
+    images: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DatasetArgs {
+    /// Root directory of the dataset
+    #[arg(long)]
+    output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// Root directory of the dataset
+    #[arg(long)]
+    output: PathBuf,
+    /// CLIP tokens per caption line the trainer keeps. Defaults to the project's setting, or 75
+    #[arg(long, value_parser = parse_max_token_length)]
+    max_token_length: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Root directory of the dataset
+    #[arg(long)]
+    output: PathBuf,
+    #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+    format: ExportFormat,
+    /// File to write the manifest to. Defaults to stdout
+    #[arg(long)]
+    to: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    Jsonl,
+    Csv,
+}
+
+/// Parses a class like `10:dog` or its directory name `10_dog`
+fn parse_class(value: &str) -> Result<Class, String> {
+    let class = Class::from_dir_name(&value.replacen(':', "_", 1)).ok_or_else(|| {
+        format!("{value:?} isn't of the form {{repeats}}:{{label}} or {{repeats}}_{{label}}, e.g. 10:dog")
+    })?;
+
+    Class::validated(&class.label, class.repeats, []).map_err(|e| e.to_string())
+}
+
+fn parse_max_token_length(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|max| tokens::MAX_TOKEN_LENGTHS.contains(max))
+        .ok_or_else(|| format!("must be one of {:?}", tokens::MAX_TOKEN_LENGTHS))
+}
+
+pub fn run(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Label(args) => label(args),
+        Command::Stats(args) => stats(&args.output),
+        Command::Validate(args) => validate(args),
+        Command::Export(args) => export(args),
+    };
+
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        ExitCode::FAILURE
+    })
+}
+
+/// Project in `output_dir`, if there is one
+fn load_project(output_dir: &Path) -> Result<Option<Project>, Error> {
+    let path = Project::path(output_dir);
+
+    path.exists().then(|| Project::load(&path)).transpose()
+}
+
+/// Dataset in `output_dir`, with the settings of its project if there is one
+fn open(output_dir: &Path) -> Result<Dataset, Error> {
+    Ok(match load_project(output_dir)? {
+        // The directory may have been moved since the project was saved
+        Some(project) => Dataset {
+            output_dir: output_dir.to_path_buf(),
+            ..project.session.dataset
+        },
+        None => Dataset::new(PathBuf::new(), output_dir),
+    })
+}
+
+fn label(args: LabelArgs) -> Result<ExitCode, Error> {
+    let caption = match &args.caption_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(Error::io(format!("Failed to read caption file {path:#?}")))?
+            .trim_end()
+            .to_string(),
+        None => args.caption.unwrap_or_default(),
+    };
+
+    let mut project = load_project(&args.output)?;
+    let defaults = project
+        .as_ref()
+        .map(|project| project.session.dataset.clone())
+        .unwrap_or_default();
+    let class = match &args.trigger {
+        Some(trigger) => args.class.with_trigger(trigger),
+        None => defaults
+            .classes
+            .iter()
+            .find(|known| known.same_dir(&args.class))
+            .cloned()
+            .unwrap_or(args.class),
+    };
+    let caption = class.insert_trigger(&caption);
+    let dataset = Dataset {
+        transfer_mode: args.transfer_mode.unwrap_or(defaults.transfer_mode),
+        collision_policy: args.collision.unwrap_or(defaults.collision_policy),
+        sidecar_extensions: defaults.sidecar_extensions,
+        caption_outputs: defaults.caption_outputs,
+        xmp: args.xmp.unwrap_or(defaults.xmp),
+        classes: vec![class],
+        ..Dataset::new(args.input, args.output.clone())
+    };
+    dataset.prepare()?;
+
+    // Only a project labeling the same input directory knows which images were already dealt with
+    let mut progress = project
+        .as_mut()
+        .filter(|project| project.session.dataset.input_dir == dataset.input_dir)
+        .map(|project| &mut project.progress);
+
+    let images = if args.images.is_empty() {
+        dataset.index()?
+    } else {
+        args.images
+    };
+
+    let mut labeled = 0;
+    let mut skipped = 0;
+    for image in images {
+        if progress
+            .as_ref()
+            .is_some_and(|progress| progress.left_in_place.contains(&image))
+        {
+            continue;
+        }
+
+        match dataset.label(&image, &dataset.classes[0], &caption)? {
+            Some(entry) => {
+                println!(
+                    "{image:?} -> {:?}",
+                    entry.destination.as_deref().unwrap_or(&image)
+                );
+                if let Some(progress) = progress.as_mut() {
+                    progress.record_applied(&entry);
+                }
+                labeled += 1;
+            }
+            None => {
+                eprintln!("Skipped {image:?}, its name is taken in the class directory");
+                skipped += 1;
+            }
+        }
+    }
+
+    if let Some(project) = &project {
+        project.save()?;
+    }
+
+    println!("Labeled {labeled} images, skipped {skipped}");
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn stats(output_dir: &Path) -> Result<ExitCode, Error> {
+    let scan = open(output_dir)?.scan()?;
+
+    let width = scan
+        .classes
+        .iter()
+        .map(|dir| dir.class.label.len())
+        .chain(["Class".len()])
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:width$}  {:>7}  {:>7}  {:>9}  {:>7}",
+        "Class", "Repeats", "Images", "Captioned", "Steps"
+    );
+    for dir in &scan.classes {
+        println!(
+            "{:width$}  {:>7}  {:>7}  {:>9}  {:>7}",
+            dir.class.label,
+            dir.class.repeats,
+            dir.images.len(),
+            dir.captioned(),
+            dir.images.len() * dir.class.repeats,
+        );
+    }
+    println!(
+        "{:width$}  {:>7}  {:>7}  {:>9}  {:>7}",
+        "Total",
+        "",
+        scan.images(),
+        scan.classes
+            .iter()
+            .map(|dir| dir.captioned())
+            .sum::<usize>(),
+        scan.steps(),
+    );
+
+    for path in &scan.unrecognized {
+        eprintln!("Ignored {path:?}, it isn't named {{repeats}}_{{label}}");
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(args: ValidateArgs) -> Result<ExitCode, Error> {
+    let mut dataset = open(&args.output)?;
+    if let Some(max) = args.max_token_length {
+        dataset.max_token_length = max;
+    }
+
+    let problems = dataset.validate()?;
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    if problems.is_empty() {
+        println!("No problems found");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("{} problems found", problems.len());
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn export(args: ExportArgs) -> Result<ExitCode, Error> {
+    let scan = open(&args.output)?.scan()?;
+
+    let mut manifest = String::new();
+    if let ExportFormat::Csv = args.format {
+        manifest.push_str("image,class,repeats,caption\n");
+    }
+
+    for dir in &scan.classes {
+        for image in &dir.images {
+            // The first caption file, as the manifest has room for one caption
+            let caption = dir.caption_extensions.first().and_then(|extension| {
+                std::fs::read_to_string(image.with_extension(extension)).ok()
+            });
+
+            match args.format {
+                ExportFormat::Jsonl => {
+                    let row = serde_json::json!({
+                        "image": image,
+                        "class": dir.class.label,
+                        "repeats": dir.class.repeats,
+                        "caption": caption,
+                    });
+                    manifest.push_str(&row.to_string());
+                }
+                ExportFormat::Csv => {
+                    let fields = [
+                        image.to_string_lossy().to_string(),
+                        dir.class.label.clone(),
+                        dir.class.repeats.to_string(),
+                        caption.unwrap_or_default(),
+                    ];
+                    let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                    manifest.push_str(&fields.join(","));
+                }
+            }
+            manifest.push('\n');
+        }
+    }
+
+    match &args.to {
+        Some(path) => fsops::write_atomic(path, manifest)?,
+        None => std::io::stdout()
+            .write_all(manifest.as_bytes())
+            .map_err(Error::io("Failed to write manifest"))?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Quotes `field` if it contains anything that would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{error::Error, fsops};
+
+/// What to do when a file with the same name already exists at the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionPolicy {
+    /// Append `_1`, `_2`, ... to the file name
+    #[default]
+    AutoSuffix,
+    /// Name every file after a hash of its content
+    ContentHash,
+    /// Name every file `{class}_0001`, `{class}_0002`, ...
+    Sequential,
+    /// Replace the existing file
+    Overwrite,
+    /// Ask every time
+    Ask,
+}
+
+impl CollisionPolicy {
+    pub const ALL: [CollisionPolicy; 5] = [
+        CollisionPolicy::AutoSuffix,
+        CollisionPolicy::ContentHash,
+        CollisionPolicy::Sequential,
+        CollisionPolicy::Overwrite,
+        CollisionPolicy::Ask,
+    ];
+}
+
+impl Display for CollisionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CollisionPolicy::AutoSuffix => "Add suffix",
+            CollisionPolicy::ContentHash => "Rename to content hash",
+            CollisionPolicy::Sequential => "Number sequentially",
+            CollisionPolicy::Overwrite => "Overwrite",
+            CollisionPolicy::Ask => "Ask",
+        })
+    }
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "suffix" => Ok(CollisionPolicy::AutoSuffix),
+            "hash" => Ok(CollisionPolicy::ContentHash),
+            "sequential" => Ok(CollisionPolicy::Sequential),
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "ask" => Ok(CollisionPolicy::Ask),
+            _ => Err(format!(
+                "Unknown collision policy {s:?}, expected one of suffix, hash, sequential, overwrite or ask"
+            )),
+        }
+    }
+}
+
+/// Picks the path `source` should be moved to inside `directory`.
+///
+/// The captions with `caption_extensions` next to the destination must be free as well.
+/// `prefix` names sequentially numbered files. Returns None if the policy is to ask and the
+/// original file name is taken.
+pub fn resolve(
+    policy: CollisionPolicy,
+    source: &Path,
+    directory: &Path,
+    prefix: &str,
+    caption_extensions: &[String],
+) -> Result<Option<PathBuf>, Error> {
+    resolve_avoiding(
+        policy,
+        source,
+        directory,
+        prefix,
+        caption_extensions,
+        &HashSet::new(),
+    )
+}
+
+/// Like [`resolve`], but the paths in `planned` count as taken as well, for files that are yet to
+/// be moved there
+pub fn resolve_avoiding(
+    policy: CollisionPolicy,
+    source: &Path,
+    directory: &Path,
+    prefix: &str,
+    caption_extensions: &[String],
+    planned: &HashSet<PathBuf>,
+) -> Result<Option<PathBuf>, Error> {
+    let extension = source
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let stem = source
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let taken = |path: &Path| fsops::exists(path) || planned.contains(path);
+    let is_free = |path: &Path| {
+        !taken(path)
+            && caption_extensions
+                .iter()
+                .all(|caption| !taken(&path.with_extension(caption)))
+    };
+    let first_free = |stem: &str| {
+        (1..)
+            .map(|n| directory.join(format!("{stem}_{n}{extension}")))
+            .find(|path| is_free(path))
+            .expect("Ran out of suffixes?!")
+    };
+
+    let original = directory.join(format!("{stem}{extension}"));
+    Ok(match policy {
+        CollisionPolicy::Overwrite => Some(original),
+        CollisionPolicy::Ask => is_free(&original).then_some(original),
+        CollisionPolicy::AutoSuffix if is_free(&original) => Some(original),
+        CollisionPolicy::AutoSuffix => Some(first_free(&stem)),
+        CollisionPolicy::ContentHash => {
+            let hash = content_hash(source)?;
+            let hashed = directory.join(format!("{hash}{extension}"));
+
+            if is_free(&hashed) {
+                Some(hashed)
+            } else {
+                Some(first_free(&hash))
+            }
+        }
+        CollisionPolicy::Sequential => {
+            let first = last_sequence_number(directory, prefix)? + 1;
+
+            (first..)
+                .map(|n| directory.join(format!("{prefix}_{n:04}{extension}")))
+                .find(|path| is_free(path))
+        }
+    })
+}
+
+/// First 16 hex digits of the SHA-256 of the file at `path`
+fn content_hash(path: &Path) -> Result<String, Error> {
+    let contents =
+        std::fs::read(path).map_err(Error::io(format!("Failed to read image {path:#?}")))?;
+
+    Ok(Sha256::digest(contents)
+        .iter()
+        .take(8)
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Highest `n` of the files named `{prefix}_{n}` in `directory`, or 0
+fn last_sequence_number(directory: &Path, prefix: &str) -> Result<usize, Error> {
+    let read_dir = std::fs::read_dir(directory).map_err(Error::io(format!(
+        "Failed to read directory {directory:#?}"
+    )))?;
+
+    Ok(read_dir
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let stem = path.file_stem()?.to_string_lossy().to_string();
+
+            stem.strip_prefix(prefix)?
+                .strip_prefix('_')?
+                .parse::<usize>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0))
+}
@@ -0,0 +1,692 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{self, CollisionPolicy},
+    error::Error,
+    fsops::{self, TransferMode},
+    journal::{self, JournalEntry, MergePlan, PlannedMove},
+    tokens,
+    xmp::{self, XmpSink},
+};
+
+/// Extensions of the files picked up from the input directory
+pub const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Extension of the caption written next to each image, unless configured otherwise
+pub const CAPTION_EXTENSION: &str = "txt";
+
+/// Field feeding the caption written next to each image, unless configured otherwise
+pub const DEFAULT_CAPTION_FIELD: &str = "Prompt";
+
+/// Placeholder in a caption that is replaced by the trigger of its class
+pub const TRIGGER_PLACEHOLDER: &str = "{trigger}";
+
+/// Placeholder in a caption that is replaced by the label of its class
+pub const CLASS_PLACEHOLDER: &str = "{class}";
+
+/// Longest directory name most filesystems allow, in bytes
+const MAX_DIR_NAME: usize = 255;
+
+/// Why a class can't be used as entered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassError {
+    /// Nothing is left of the label once it is sanitized
+    EmptyLabel,
+    ZeroRepeats,
+    /// The directory name is too long for most filesystems
+    TooLong,
+    /// Another class already uses the label, possibly with different case or repeats
+    Duplicate(Class),
+}
+
+impl Display for ClassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClassError::EmptyLabel => f.write_str("The class name is empty"),
+            ClassError::ZeroRepeats => f.write_str("Repeats must be at least 1"),
+            ClassError::TooLong => write!(
+                f,
+                "The directory name would be longer than {MAX_DIR_NAME} bytes"
+            ),
+            ClassError::Duplicate(other) => {
+                write!(f, "{:?} is already a class ({other})", other.label)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClassError {}
+
+/// A class of images, stored in `{repeats}_{label}` inside the output root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Class {
+    pub label: String,
+    pub repeats: usize,
+    /// Token the model learns the class by, e.g. `ohwx dog`, which every caption should contain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+}
+
+impl Class {
+    pub fn new(label: impl Into<String>, repeats: usize) -> Self {
+        Self {
+            label: label.into(),
+            repeats,
+            trigger: None,
+        }
+    }
+
+    /// The class with `trigger`, or without one if it is blank
+    pub fn with_trigger(self, trigger: &str) -> Self {
+        let trigger = trigger.trim();
+
+        Self {
+            trigger: (!trigger.is_empty()).then(|| trigger.to_string()),
+            ..self
+        }
+    }
+
+    /// Checks and sanitizes a class entered by the user, which must not share its label with any
+    /// of `others`
+    pub fn validated<'a>(
+        label: &str,
+        repeats: usize,
+        others: impl IntoIterator<Item = &'a Class>,
+    ) -> Result<Self, ClassError> {
+        let class = Self::new(Self::sanitize_label(label), repeats);
+
+        if class.label.is_empty() {
+            return Err(ClassError::EmptyLabel);
+        }
+        if repeats == 0 {
+            return Err(ClassError::ZeroRepeats);
+        }
+        if class.to_string().len() > MAX_DIR_NAME {
+            return Err(ClassError::TooLong);
+        }
+
+        // Directories differing only in case are the same on Windows and macOS
+        if let Some(other) = others
+            .into_iter()
+            .find(|other| other.label.to_lowercase() == class.label.to_lowercase())
+        {
+            return Err(ClassError::Duplicate(other.clone()));
+        }
+
+        Ok(class)
+    }
+
+    /// Makes `label` usable as part of a directory name on every platform.
+    ///
+    /// Path separators and characters Windows doesn't allow become `_`, surrounding whitespace
+    /// and trailing dots are dropped. Reserved names like `CON` don't need handling, as the
+    /// directory name always starts with the repeats.
+    pub fn sanitize_label(label: &str) -> String {
+        let replaced: String = label
+            .chars()
+            .map(|c| {
+                if c.is_control()
+                    || matches!(c, '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*')
+                {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        replaced
+            .trim()
+            .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+            .to_string()
+    }
+
+    /// Parses a directory name of the form `{repeats}_{label}`
+    pub fn from_dir_name(name: &str) -> Option<Self> {
+        let (repeats, label) = name.split_once('_')?;
+        let repeats = repeats.parse().ok().filter(|repeats| *repeats != 0)?;
+
+        (!label.is_empty()).then(|| Self::new(label, repeats))
+    }
+
+    /// `caption` with the trigger in place of `{trigger}` and the label in place of `{class}`
+    pub fn fill_placeholders(&self, caption: &str) -> String {
+        let caption = caption.replace(CLASS_PLACEHOLDER, &self.label);
+
+        match &self.trigger {
+            Some(trigger) => caption.replace(TRIGGER_PLACEHOLDER, trigger),
+            None => caption,
+        }
+    }
+
+    /// [`Class::fill_placeholders`], with the trigger put in front of `caption` if it doesn't
+    /// mention the trigger at all
+    pub fn insert_trigger(&self, caption: &str) -> String {
+        let caption = self.fill_placeholders(caption);
+
+        match &self.trigger {
+            Some(trigger) if !self.mentions_trigger(&caption) => {
+                if caption.trim().is_empty() {
+                    trigger.clone()
+                } else {
+                    format!("{trigger}, {caption}")
+                }
+            }
+            _ => caption,
+        }
+    }
+
+    /// Whether `caption` contains the trigger, which it always does if there is none
+    pub fn mentions_trigger(&self, caption: &str) -> bool {
+        self.trigger
+            .as_ref()
+            .is_none_or(|trigger| caption.contains(trigger.as_str()))
+    }
+
+    /// Whether `other` is stored in the same directory
+    pub fn same_dir(&self, other: &Class) -> bool {
+        self.label == other.label && self.repeats == other.repeats
+    }
+
+    /// Directory of the class inside `output_root`
+    pub fn path(&self, output_root: &Path) -> PathBuf {
+        output_root.join(self.to_string())
+    }
+}
+
+impl Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.repeats, self.label)
+    }
+}
+
+/// A caption file written next to each labeled image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptionOutput {
+    /// Extension of the caption file, e.g. `txt` or `caption`
+    pub extension: String,
+    /// Name of the text field written into the file. Outputs with the same field get the same
+    /// text
+    pub field: String,
+}
+
+impl CaptionOutput {
+    pub fn new(extension: &str, field: &str) -> Self {
+        Self {
+            extension: extension.trim().trim_start_matches('.').to_string(),
+            field: field.trim().to_string(),
+        }
+    }
+
+    /// Checks a caption output entered by the user, whose extension must not be used by any of
+    /// `others`
+    pub fn validated<'a>(
+        extension: &str,
+        field: &str,
+        others: impl IntoIterator<Item = &'a CaptionOutput>,
+    ) -> Result<Self, CaptionOutputError> {
+        let output = Self::new(extension, field);
+        let lowercase = output.extension.to_lowercase();
+
+        if output.extension.is_empty() {
+            return Err(CaptionOutputError::EmptyExtension);
+        }
+        if output.extension.chars().any(|c| {
+            c.is_whitespace()
+                || c.is_control()
+                || matches!(
+                    c,
+                    '.' | '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*'
+                )
+        }) {
+            return Err(CaptionOutputError::InvalidExtension);
+        }
+        if IMAGE_EXTENSIONS.contains(&lowercase.as_str()) || lowercase == "xmp" {
+            return Err(CaptionOutputError::ReservedExtension);
+        }
+        if output.field.is_empty() {
+            return Err(CaptionOutputError::EmptyField);
+        }
+        if others
+            .into_iter()
+            .any(|other| other.extension.to_lowercase() == lowercase)
+        {
+            return Err(CaptionOutputError::Duplicate);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Why a caption output can't be used as entered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptionOutputError {
+    EmptyExtension,
+    /// The extension contains dots, whitespace or characters file names can't have
+    InvalidExtension,
+    /// The extension is used by images or XMP sidecars
+    ReservedExtension,
+    EmptyField,
+    /// Another output already writes files with the extension
+    Duplicate,
+}
+
+impl Display for CaptionOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptionOutputError::EmptyExtension => f.write_str("The extension is empty"),
+            CaptionOutputError::InvalidExtension => f.write_str(
+                "The extension can't contain dots, whitespace or characters like / or :",
+            ),
+            CaptionOutputError::ReservedExtension => {
+                f.write_str("The extension is used by images or XMP sidecars")
+            }
+            CaptionOutputError::EmptyField => f.write_str("The field name is empty"),
+            CaptionOutputError::Duplicate => f.write_str("Another caption file has the extension"),
+        }
+    }
+}
+
+impl std::error::Error for CaptionOutputError {}
+
+impl Display for CaptionOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ".{} <- {}", self.extension, self.field)
+    }
+}
+
+/// Extensions of captions other tools commonly leave next to images
+pub const DEFAULT_SIDECAR_EXTENSIONS: [&str; 2] = ["txt", "caption"];
+
+/// Where images come from, where they go and how they get there
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+    /// Dumping directory with images
+    pub input_dir: PathBuf,
+    /// Root directory of dreambooth-style dataset
+    pub output_dir: PathBuf,
+    /// If None, trashed images will be left in input_dir
+    pub trash_dir: Option<PathBuf>,
+    /// How images are transferred out of input_dir
+    #[serde(default)]
+    pub transfer_mode: TransferMode,
+    pub classes: Vec<Class>,
+    #[serde(default)]
+    pub collision_policy: CollisionPolicy,
+    /// Extensions of captions next to images in input_dir, which pre-fill the prompt and go
+    /// along with the image, in order of preference
+    #[serde(default = "default_sidecar_extensions")]
+    pub sidecar_extensions: Vec<String>,
+    /// Where captions are written as XMP as well
+    #[serde(default)]
+    pub xmp: XmpSink,
+    /// Caption files written next to each labeled image
+    #[serde(default = "default_caption_outputs")]
+    pub caption_outputs: Vec<CaptionOutput>,
+    /// CLIP tokens of each caption line the trainer keeps, its `--max_token_length`
+    #[serde(default = "default_max_token_length")]
+    pub max_token_length: usize,
+}
+
+fn default_sidecar_extensions() -> Vec<String> {
+    DEFAULT_SIDECAR_EXTENSIONS.map(String::from).to_vec()
+}
+
+fn default_max_token_length() -> usize {
+    tokens::MAX_TOKEN_LENGTHS[0]
+}
+
+fn default_caption_outputs() -> Vec<CaptionOutput> {
+    vec![CaptionOutput::new(CAPTION_EXTENSION, DEFAULT_CAPTION_FIELD)]
+}
+
+impl Default for Dataset {
+    fn default() -> Self {
+        Self {
+            input_dir: PathBuf::new(),
+            output_dir: PathBuf::new(),
+            trash_dir: None,
+            transfer_mode: TransferMode::default(),
+            classes: Vec::new(),
+            collision_policy: CollisionPolicy::default(),
+            sidecar_extensions: default_sidecar_extensions(),
+            xmp: XmpSink::default(),
+            caption_outputs: default_caption_outputs(),
+            max_token_length: default_max_token_length(),
+        }
+    }
+}
+
+impl Dataset {
+    pub fn new(input_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            input_dir: input_dir.into(),
+            output_dir: output_dir.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Names of the text fields feeding the caption outputs, in the order they first appear
+    pub fn caption_fields(&self) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        for output in &self.caption_outputs {
+            if !fields.contains(&output.field) {
+                fields.push(output.field.clone());
+            }
+        }
+
+        fields
+    }
+
+    /// Extensions of the caption outputs
+    pub fn caption_extensions(&self) -> Vec<String> {
+        self.caption_outputs
+            .iter()
+            .map(|output| output.extension.clone())
+            .collect()
+    }
+
+    /// Images in the input directory, sorted by path
+    pub fn index(&self) -> Result<Vec<PathBuf>, Error> {
+        list_images(&self.input_dir)
+    }
+
+    /// Finishes whatever was interrupted last time and creates the class directories
+    pub fn prepare(&self) -> Result<(), Error> {
+        journal::recover(&self.output_dir)?;
+
+        for class in &self.classes {
+            let class_dir = class.path(&self.output_dir);
+            std::fs::create_dir_all(&class_dir).map_err(Error::io(format!(
+                "Unable to create directory {class_dir:#?}"
+            )))?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the class at `index` with `class`, renaming its directory.
+    ///
+    /// If a directory that isn't a class yet is already named like `class`, the images are
+    /// merged into it.
+    pub fn edit_class(&mut self, index: usize, class: Class) -> Result<(), Error> {
+        if self
+            .classes
+            .iter()
+            .enumerate()
+            .any(|(other, existing)| other != index && existing.same_dir(&class))
+        {
+            return Err(Error::Conflict(format!(
+                "There already is a class {:?} with {} repeats, merge into it instead",
+                class.label, class.repeats
+            )));
+        }
+
+        let from = self.classes[index].path(&self.output_dir);
+        let to = class.path(&self.output_dir);
+        if from != to && fsops::exists(&from) {
+            if fsops::exists(&to) {
+                plan_merge(&from, &to, &self.caption_extensions())?.apply(&self.output_dir)?;
+            } else {
+                fsops::rename_dir(&from, &to)?;
+            }
+        }
+
+        self.classes[index] = class;
+
+        Ok(())
+    }
+
+    /// Moves the images and captions of the class at `from` into the class at `into`, and
+    /// removes the class at `from`.
+    ///
+    /// Images keep their captions. Names that are taken get a suffix.
+    pub fn merge_classes(&mut self, from: usize, into: usize) -> Result<(), Error> {
+        let from_dir = self.classes[from].path(&self.output_dir);
+        let into_dir = self.classes[into].path(&self.output_dir);
+
+        if from_dir != into_dir && fsops::exists(&from_dir) {
+            std::fs::create_dir_all(&into_dir).map_err(Error::io(format!(
+                "Unable to create directory {into_dir:#?}"
+            )))?;
+            plan_merge(&from_dir, &into_dir, &self.caption_extensions())?
+                .apply(&self.output_dir)?;
+        }
+
+        self.classes.remove(from);
+
+        Ok(())
+    }
+
+    /// Move of `image` into the directory of `class`, with `caption` written into every caption
+    /// output
+    pub fn plan_label(&self, image: &Path, class: &Class, caption: &str) -> PlannedMove {
+        let fields = self.caption_fields();
+
+        self.plan_label_fields(image, class, &vec![caption.to_string(); fields.len()])
+    }
+
+    /// Move of `image` into the directory of `class`, with `captions` holding the text of each of
+    /// [`Dataset::caption_fields`]
+    pub fn plan_label_fields(
+        &self,
+        image: &Path,
+        class: &Class,
+        captions: &[String],
+    ) -> PlannedMove {
+        let fields = self.caption_fields();
+        let caption_of = |field: &String| {
+            fields
+                .iter()
+                .position(|known| known == field)
+                .and_then(|index| captions.get(index))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        PlannedMove {
+            source: image.to_path_buf(),
+            directory: Some(class.path(&self.output_dir)),
+            prefix: class.label.clone(),
+            captions: self
+                .caption_outputs
+                .iter()
+                .map(|output| (output.extension.clone(), caption_of(&output.field)))
+                .collect(),
+            mode: self.transfer_mode,
+            prompts: captions.to_vec(),
+            deferred: false,
+            trashed: false,
+            sidecar_extensions: self.sidecar_extensions.clone(),
+            xmp: self.xmp,
+            keywords: vec![class.label.clone()],
+        }
+    }
+
+    /// Move of `image` into the trash directory
+    pub fn plan_trash(&self, image: &Path) -> PlannedMove {
+        PlannedMove {
+            source: image.to_path_buf(),
+            // If there isn't a trash dir, have trashed images remain in input dir
+            directory: self.trash_dir.clone(),
+            prefix: "trash".into(),
+            captions: Vec::new(),
+            mode: self.transfer_mode,
+            prompts: Vec::new(),
+            deferred: false,
+            trashed: true,
+            sidecar_extensions: self.sidecar_extensions.clone(),
+            xmp: XmpSink::Off,
+            keywords: Vec::new(),
+        }
+    }
+
+    /// Contents of the first sidecar caption next to `image`, if there is one that isn't empty
+    pub fn sidecar_caption(&self, image: &Path) -> Option<String> {
+        first_caption(&find_sidecars(image, &self.sidecar_extensions))
+    }
+
+    /// Like [`Dataset::sidecar_caption`], but only from sidecars with the extension of an output
+    /// of `field`
+    pub fn field_sidecar_caption(&self, image: &Path, field: &str) -> Option<String> {
+        let extensions: Vec<String> = self
+            .sidecar_extensions
+            .iter()
+            .filter(|extension| {
+                self.caption_outputs
+                    .iter()
+                    .any(|output| output.field == field && &&output.extension == extension)
+            })
+            .cloned()
+            .collect();
+
+        first_caption(&find_sidecars(image, &extensions))
+    }
+
+    /// Transfers `image` into the directory of `class` and writes `caption` next to it.
+    ///
+    /// Returns None without touching anything if the collision policy is to ask and the file
+    /// name is taken.
+    pub fn label(
+        &self,
+        image: &Path,
+        class: &Class,
+        caption: &str,
+    ) -> Result<Option<JournalEntry>, Error> {
+        self.perform(&self.plan_label(image, class, caption))
+    }
+
+    /// Transfers `image` into the trash directory, like [`Dataset::label`]
+    pub fn trash(&self, image: &Path) -> Result<Option<JournalEntry>, Error> {
+        self.perform(&self.plan_trash(image))
+    }
+
+    /// Reverts a labeled or trashed image
+    pub fn undo(&self, entry: &JournalEntry) -> Result<(), Error> {
+        entry.revert(&self.output_dir)
+    }
+
+    fn perform(&self, planned: &PlannedMove) -> Result<Option<JournalEntry>, Error> {
+        let Some(entry) = planned.resolve(self.collision_policy)? else {
+            return Ok(None);
+        };
+
+        entry.apply(&self.output_dir)?;
+
+        Ok(Some(entry))
+    }
+}
+
+/// Images directly inside `directory`, sorted by path
+pub fn list_images(directory: &Path) -> Result<Vec<PathBuf>, Error> {
+    let read_dir = std::fs::read_dir(directory).map_err(Error::io(format!(
+        "Failed to read directory {directory:#?}"
+    )))?;
+
+    let mut images = Vec::new();
+    for entry in read_dir {
+        let path = entry.map_err(Error::io("Failed to read entry"))?.path();
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+
+        if path.is_file() && IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            images.push(path);
+        }
+    }
+    images.sort();
+
+    Ok(images)
+}
+
+/// Files next to `image` with one of `extensions`, in the order of `extensions`
+pub fn find_sidecars(image: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    extensions
+        .iter()
+        .map(|extension| image.with_extension(extension))
+        .filter(|path| path != image && path.is_file())
+        .collect()
+}
+
+/// Contents of the first of `captions` that isn't empty
+fn first_caption(captions: &[PathBuf]) -> Option<String> {
+    captions
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .map(|contents| contents.trim().to_string())
+        .find(|contents| !contents.is_empty())
+}
+
+/// Moves of every file in `from` into `into`, with names that are taken getting a suffix.
+///
+/// Images come first, followed by their captions and XMP sidecars under the name the image gets.
+fn plan_merge(from: &Path, into: &Path, caption_extensions: &[String]) -> Result<MergePlan, Error> {
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    // Destinations of the files planned so far, which aren't there yet
+    let mut planned = HashSet::new();
+    let mut free_name = |path: &Path, caption_extensions: &[String]| {
+        let destination = collision::resolve_avoiding(
+            CollisionPolicy::AutoSuffix,
+            path,
+            into,
+            "",
+            caption_extensions,
+            &planned,
+        )?
+        .expect("Suffixes always find a name");
+        planned.insert(destination.clone());
+        for extension in caption_extensions {
+            planned.insert(destination.with_extension(extension));
+        }
+
+        Ok::<_, Error>(destination)
+    };
+
+    for image in list_images(from)? {
+        let destination = free_name(&image, caption_extensions)?;
+
+        let captions = caption_extensions
+            .iter()
+            .map(|extension| {
+                (
+                    image.with_extension(extension),
+                    destination.with_extension(extension),
+                )
+            })
+            .chain([(xmp::sidecar_path(&image), xmp::sidecar_path(&destination))])
+            .filter(|(caption, _)| caption.is_file())
+            .collect::<Vec<_>>();
+        moves.push((image, destination));
+        moves.extend(captions);
+    }
+
+    // Captions without an image, and whatever else training tools put there
+    let read_dir = std::fs::read_dir(from)
+        .map_err(Error::io(format!("Failed to read directory {from:#?}")))?;
+    let sources: HashSet<&PathBuf> = moves.iter().map(|(source, _)| source).collect();
+    let mut others = Vec::new();
+    for entry in read_dir {
+        let path = entry.map_err(Error::io("Failed to read entry"))?.path();
+        if path.is_file() && !sources.contains(&path) {
+            others.push(path);
+        }
+    }
+    others.sort();
+    for path in others {
+        let destination = free_name(&path, &[])?;
+        moves.push((path, destination));
+    }
+
+    Ok(MergePlan {
+        from: from.to_path_buf(),
+        moves,
+    })
+}
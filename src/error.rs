@@ -0,0 +1,63 @@
+use std::{fmt::Display, io, sync::Arc};
+
+/// Something that went wrong while working with the dataset
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// A filesystem operation failed
+    Io {
+        context: String,
+        source: Arc<io::Error>,
+    },
+    /// A file couldn't be parsed or serialized
+    Format { context: String, reason: String },
+    /// The request doesn't fit the files on disk, e.g. a directory is already taken
+    Conflict(String),
+    /// The dataset is in a state that can't be fixed without the user stepping in
+    Unrecoverable(String),
+}
+
+impl Error {
+    /// Wraps an I/O error, for use with `map_err`
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Error {
+        let context = context.into();
+        move |source| Error::Io {
+            context,
+            source: Arc::new(source),
+        }
+    }
+
+    /// Wraps a (de)serialization error, for use with `map_err`
+    pub fn format<E: Display>(context: impl Into<String>) -> impl FnOnce(E) -> Error {
+        let context = context.into();
+        move |reason| Error::Format {
+            context,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Whether retrying or skipping could get past this error
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, Error::Unrecoverable(..))
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { context, source } => write!(f, "{context}: {source}"),
+            Error::Format { context, reason } => write!(f, "{context}: {reason}"),
+            Error::Conflict(description) | Error::Unrecoverable(description) => {
+                f.write_str(description)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
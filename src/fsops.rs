@@ -0,0 +1,193 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// How images get from the input directory into the dataset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferMode {
+    /// Move the image, removing it from the input directory
+    #[default]
+    Move,
+    /// Copy the image, leaving the input directory untouched
+    Copy,
+    Hardlink,
+    Symlink,
+}
+
+impl TransferMode {
+    pub const ALL: [TransferMode; 4] = [
+        TransferMode::Move,
+        TransferMode::Copy,
+        TransferMode::Hardlink,
+        TransferMode::Symlink,
+    ];
+
+    /// Transfers `from` to `to` according to the mode
+    pub fn transfer(self, from: &Path, to: &Path) -> Result<(), Error> {
+        match self {
+            TransferMode::Move => move_file(from, to),
+            TransferMode::Copy => copy_file(from, to),
+            TransferMode::Hardlink => {
+                remove_existing(to)?;
+                match std::fs::hard_link(from, to) {
+                    Ok(()) => Ok(()),
+                    // Links can't cross file systems, a copy leaves the input untouched as well
+                    Err(e) if e.kind() == ErrorKind::CrossesDevices => copy_file(from, to),
+                    Err(e) => Err(Error::io(format!("Failed to link {from:#?} to {to:#?}"))(e)),
+                }
+            }
+            TransferMode::Symlink => {
+                remove_existing(to)?;
+                symlink(from, to)
+            }
+        }
+    }
+}
+
+impl Display for TransferMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TransferMode::Move => "Move",
+            TransferMode::Copy => "Copy",
+            TransferMode::Hardlink => "Hardlink",
+            TransferMode::Symlink => "Symlink",
+        })
+    }
+}
+
+impl FromStr for TransferMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransferMode::ALL
+            .into_iter()
+            .find(|mode| mode.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown transfer mode {s:?}, expected one of move, copy, hardlink or symlink"
+                )
+            })
+    }
+}
+
+/// Removes `path` if it exists, as links can't replace files the way renames do
+fn remove_existing(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::io(format!("Failed to remove {path:#?}"))(e)),
+    }
+}
+
+/// Like [`Path::exists`], but also true for dangling symlinks
+pub fn exists(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok()
+}
+
+/// Path of the temporary file used while writing `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".quicklabel-tmp");
+
+    path.with_file_name(name)
+}
+
+/// Flushes the directory entry of `path`, so a rename into it survives a crash
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .map_err(Error::io(format!("Failed to sync directory {parent:#?}")))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+/// Moves a file, falling back to copying it when `from` and `to` are on different filesystems.
+///
+/// `to` either doesn't exist or is complete, even if the process dies halfway through.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    match std::fs::rename(from, to) {
+        Ok(()) => return sync_parent(to),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(Error::io(format!("Failed to move {from:#?} to {to:#?}"))(e)),
+    }
+
+    copy_file(from, to)?;
+
+    std::fs::remove_file(from).map_err(Error::io(format!("Failed to remove {from:#?}")))
+}
+
+/// Renames the directory `from` to `to`, which must not exist yet
+pub fn rename_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    // Renaming onto an empty directory would silently replace it
+    if exists(to) {
+        return Err(Error::Conflict(format!("{to:#?} already exists")));
+    }
+
+    std::fs::rename(from, to)
+        .map_err(Error::io(format!("Failed to rename {from:#?} to {to:#?}")))?;
+
+    sync_parent(to)
+}
+
+/// Copies a file through a temporary file, so `to` is never left incomplete
+pub fn copy_file(from: &Path, to: &Path) -> Result<(), Error> {
+    let temp = temp_path(to);
+    std::fs::copy(from, &temp)
+        .and_then(|_| File::open(&temp)?.sync_all())
+        .map_err(Error::io(format!("Failed to copy {from:#?} to {temp:#?}")))?;
+
+    std::fs::rename(&temp, to)
+        .map_err(Error::io(format!("Failed to move {temp:#?} to {to:#?}")))?;
+
+    sync_parent(to)
+}
+
+fn symlink(from: &Path, to: &Path) -> Result<(), Error> {
+    // Relative targets would resolve against the link's directory
+    let target =
+        std::path::absolute(from).map_err(Error::io(format!("Failed to resolve {from:#?}")))?;
+
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(&target, to);
+    #[cfg(windows)]
+    let result = std::os::windows::fs::symlink_file(&target, to);
+
+    result.map_err(Error::io(format!("Failed to link {to:#?} to {target:#?}")))
+}
+
+/// Writes `contents` to `path` through a temporary file, so `path` never ends up half-written
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    let temp = temp_path(path);
+
+    File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .map_err(Error::io(format!("Failed to write {temp:#?}")))?;
+
+    std::fs::rename(&temp, path)
+        .map_err(Error::io(format!("Failed to move {temp:#?} to {path:#?}")))?;
+
+    sync_parent(path)
+}
+
+/// Removes the temporary file left behind by an interrupted [`move_file`] or [`write_atomic`]
+pub fn remove_leftovers(path: &Path) -> Result<(), Error> {
+    remove_existing(&temp_path(path))
+}
@@ -0,0 +1,527 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{self, CollisionPolicy},
+    dataset::find_sidecars,
+    error::Error,
+    fsops::{self, TransferMode},
+    xmp::{self, XmpCaption, XmpSink},
+};
+
+/// Name of the file recording the operation in progress, stored in the output root
+pub const INTENT_LOG_NAME: &str = ".quicklabel-intent.toml";
+
+/// Operation that was started, but may not have finished
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Logged {
+    Transfer(Intent),
+    Merge(MergePlan),
+}
+
+/// Transfer of an image that was started
+#[derive(Debug, Serialize, Deserialize)]
+struct Intent {
+    entry: JournalEntry,
+    /// Whether the entry was being reverted rather than applied
+    revert: bool,
+}
+
+/// Moves of every file out of a class directory, decided on before any is moved so a merge can
+/// be finished after a crash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergePlan {
+    /// Directory removed once it is empty
+    pub from: PathBuf,
+    /// (from, to) of every file, images before their captions
+    pub moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl MergePlan {
+    pub fn apply(&self, output_dir: &Path) -> Result<(), Error> {
+        with_log(output_dir, &Logged::Merge(self.clone()), || self.finish())
+    }
+
+    /// Carries out the moves that haven't been yet
+    fn finish(&self) -> Result<(), Error> {
+        for (from, to) in &self.moves {
+            if fsops::exists(from) {
+                fsops::move_file(from, to)?;
+            }
+        }
+
+        if !fsops::exists(&self.from) {
+            return Ok(());
+        }
+        std::fs::remove_dir(&self.from).map_err(Error::io(format!(
+            "Failed to remove directory {:#?}",
+            self.from
+        )))
+    }
+}
+
+/// A move whose destination file name hasn't been decided yet
+#[derive(Debug, Clone)]
+pub struct PlannedMove {
+    pub source: PathBuf,
+    /// Directory the image is moved into. None to leave it where it is
+    pub directory: Option<PathBuf>,
+    /// Name of sequentially numbered files
+    pub prefix: String,
+    /// (extension, contents) of the captions written next to the image
+    pub captions: Vec<(String, String)>,
+    pub mode: TransferMode,
+    /// Text of each caption field, kept so it can be given back
+    pub prompts: Vec<String>,
+    pub deferred: bool,
+    pub trashed: bool,
+    /// Extensions of the files next to the image that go along with it
+    pub sidecar_extensions: Vec<String>,
+    /// Where the caption is written as XMP as well, with `keywords` as its subjects
+    pub xmp: XmpSink,
+    pub keywords: Vec<String>,
+}
+
+impl PlannedMove {
+    /// Decides on the destination according to `policy`. None if the user has to be asked
+    pub fn resolve(&self, policy: CollisionPolicy) -> Result<Option<JournalEntry>, Error> {
+        let caption_extensions: Vec<String> = self
+            .captions
+            .iter()
+            .map(|(extension, _)| extension.clone())
+            .collect();
+
+        let destination = match &self.directory {
+            Some(directory) => {
+                match collision::resolve(
+                    policy,
+                    &self.source,
+                    directory,
+                    &self.prefix,
+                    &caption_extensions,
+                )? {
+                    Some(destination) => Some(destination),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+
+        // Sidecars only go along if the image leaves the input directory. They are replaced by
+        // the new captions when labeling, and kept next to the image when trashing.
+        let mut sidecars = Vec::new();
+        if let Some(destination) = destination
+            .as_ref()
+            .filter(|_| self.mode == TransferMode::Move)
+        {
+            for path in find_sidecars(&self.source, &self.sidecar_extensions) {
+                // Other tools may write them in any encoding, so they are kept as they are
+                let contents = std::fs::read(&path)
+                    .map_err(Error::io(format!("Failed to read sidecar {path:#?}")))?;
+                let extension = path.extension().unwrap_or_default();
+
+                sidecars.push(Sidecar {
+                    destination: self.trashed.then(|| destination.with_extension(extension)),
+                    source: path,
+                    contents,
+                });
+            }
+        }
+
+        let xmp = match &destination {
+            Some(destination) if !self.trashed && self.xmp != XmpSink::Off => {
+                // Linked images share their contents with the original, which must stay as it is
+                let embedded = self.xmp == XmpSink::Embed
+                    && matches!(self.mode, TransferMode::Move | TransferMode::Copy)
+                    && xmp::can_embed(&self.source);
+                let caption = XmpCaption {
+                    description: self.captions.first().map(|(_, contents)| contents.clone()),
+                    subjects: self.keywords.clone(),
+                };
+
+                Some(XmpWrite {
+                    path: if embedded {
+                        destination.clone()
+                    } else {
+                        xmp::sidecar_path(destination)
+                    },
+                    embedded,
+                    packet: caption.packet(),
+                    previous: if embedded {
+                        xmp::read_embedded(&self.source)?
+                    } else {
+                        None
+                    },
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Some(JournalEntry {
+            source: self.source.clone(),
+            captions: destination
+                .iter()
+                .flat_map(|destination| {
+                    self.captions.iter().map(|(extension, contents)| Caption {
+                        path: destination.with_extension(extension),
+                        contents: contents.clone(),
+                    })
+                })
+                .collect(),
+            destination,
+            mode: self.mode,
+            prompts: self.prompts.clone(),
+            deferred: self.deferred,
+            trashed: self.trashed,
+            sidecars,
+            xmp,
+        }))
+    }
+}
+
+/// A caption written next to the destination
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Caption {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// A file next to an image in the input directory, like a caption generated by another tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub source: PathBuf,
+    /// Where the sidecar goes. None to remove it
+    pub destination: Option<PathBuf>,
+    /// Kept so the sidecar can be restored without relying on the destination
+    pub contents: Vec<u8>,
+}
+
+/// XMP written along with the caption, for photo managers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XmpWrite {
+    /// The destination image if the XMP is embedded, otherwise its sidecar
+    pub path: PathBuf,
+    pub embedded: bool,
+    pub packet: String,
+    /// Packet embedded in the image before, put back when reverting
+    pub previous: Option<String>,
+}
+
+/// A completed transfer of an image out of the input directory, recorded so it can be undone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Location of the image in the input directory
+    pub source: PathBuf,
+    /// Where the image was moved to. None if it was trashed without a trash directory
+    pub destination: Option<PathBuf>,
+    /// Captions written next to the destination
+    pub captions: Vec<Caption>,
+    pub mode: TransferMode,
+    /// Text of each caption field the image was submitted with
+    pub prompts: Vec<String>,
+    /// Whether the image came from the deferred queue
+    pub deferred: bool,
+    pub trashed: bool,
+    /// Files next to the source that go along with the image
+    #[serde(default)]
+    pub sidecars: Vec<Sidecar>,
+    #[serde(default)]
+    pub xmp: Option<XmpWrite>,
+}
+
+impl JournalEntry {
+    /// Whether the image is no longer in the input directory once applied
+    pub fn removes_source(&self) -> bool {
+        self.destination.is_some() && self.mode == TransferMode::Move
+    }
+
+    /// Performs the transfer and writes the captions
+    pub fn apply(&self, output_dir: &Path) -> Result<(), Error> {
+        with_intent(output_dir, self, false, || {
+            if let Some(destination) = &self.destination {
+                self.mode.transfer(&self.source, destination)?;
+            }
+
+            self.write_captions()?;
+            self.apply_sidecars()?;
+            self.write_xmp()
+        })
+    }
+
+    /// Moves the image back into the input directory, or removes the copy, and deletes the
+    /// captions
+    pub fn revert(&self, output_dir: &Path) -> Result<(), Error> {
+        with_intent(output_dir, self, true, || {
+            if let Some(destination) = &self.destination {
+                self.restore_xmp(destination)?;
+            }
+            self.revert_transfer()?;
+            self.remove_captions()?;
+            self.restore_sidecars()
+        })
+    }
+
+    /// Finishes or rolls back what a failed attempt at applying or, with `revert`, reverting the
+    /// entry left behind, like [`recover`] does after a crash. Whether the attempt was finished,
+    /// so it mustn't be repeated.
+    pub fn settle(&self, output_dir: &Path, revert: bool) -> Result<bool, Error> {
+        Ok(
+            finish_interrupted(output_dir)?.is_some_and(|(intent, finished)| {
+                finished && intent.revert == revert && intent.entry.source == self.source
+            }),
+        )
+    }
+
+    fn revert_transfer(&self) -> Result<(), Error> {
+        match &self.destination {
+            Some(destination) if self.mode == TransferMode::Move => {
+                fsops::move_file(destination, &self.source)
+            }
+            Some(destination) if fsops::exists(destination) => std::fs::remove_file(destination)
+                .map_err(Error::io(format!("Failed to remove {destination:#?}"))),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_captions(&self) -> Result<(), Error> {
+        for caption in &self.captions {
+            fsops::write_atomic(&caption.path, &caption.contents)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_captions(&self) -> Result<(), Error> {
+        for caption in &self.captions {
+            if caption.path.exists() {
+                std::fs::remove_file(&caption.path).map_err(Error::io(format!(
+                    "Failed to remove caption {:#?}",
+                    caption.path
+                )))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the sidecars to their destination and removes them from the input directory.
+    /// Repeating this is harmless, so it can be redone after a crash.
+    fn apply_sidecars(&self) -> Result<(), Error> {
+        for sidecar in &self.sidecars {
+            if let Some(destination) = &sidecar.destination {
+                fsops::write_atomic(destination, &sidecar.contents)?;
+            }
+            remove_if_exists(&sidecar.source)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_xmp(&self) -> Result<(), Error> {
+        match &self.xmp {
+            Some(xmp) if xmp.embedded => xmp::write_embedded(&xmp.path, Some(&xmp.packet)),
+            Some(xmp) => fsops::write_atomic(&xmp.path, &xmp.packet),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the XMP when recovering, where an image it can't be embedded in counts as done.
+    /// Embedding fails the same way every time, so retrying would only get stuck on it.
+    fn finish_xmp(&self) -> Result<(), Error> {
+        match self.write_xmp() {
+            Err(Error::Format { .. }) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Puts back the XMP embedded in the moved `image` before, or removes the XMP sidecar.
+    /// Copies are removed anyway, so they are left alone.
+    fn restore_xmp(&self, image: &Path) -> Result<(), Error> {
+        match &self.xmp {
+            Some(xmp) if xmp.embedded => {
+                if self.mode == TransferMode::Move && fsops::exists(image) {
+                    xmp::write_embedded(image, xmp.previous.as_deref())
+                } else {
+                    Ok(())
+                }
+            }
+            Some(xmp) => remove_if_exists(&xmp.path),
+            None => Ok(()),
+        }
+    }
+
+    fn restore_sidecars(&self) -> Result<(), Error> {
+        for sidecar in &self.sidecars {
+            fsops::write_atomic(&sidecar.source, &sidecar.contents)?;
+            if let Some(destination) = &sidecar.destination {
+                remove_if_exists(destination)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    std::fs::remove_file(path).map_err(Error::io(format!("Failed to remove {path:#?}")))
+}
+
+/// Records the transfer in the intent log while `steps` run, so it can be recovered after a crash
+fn with_intent(
+    output_dir: &Path,
+    entry: &JournalEntry,
+    revert: bool,
+    steps: impl FnOnce() -> Result<(), Error>,
+) -> Result<(), Error> {
+    let intent = Logged::Transfer(Intent {
+        entry: entry.clone(),
+        revert,
+    });
+
+    with_log(output_dir, &intent, steps)
+}
+
+fn with_log(
+    output_dir: &Path,
+    logged: &Logged,
+    steps: impl FnOnce() -> Result<(), Error>,
+) -> Result<(), Error> {
+    // The log of an operation that failed before mustn't be lost, or it can't be recovered
+    recover(output_dir)?;
+
+    let log = output_dir.join(INTENT_LOG_NAME);
+    fsops::write_atomic(
+        &log,
+        toml::to_string(logged).map_err(Error::format("Failed to serialize intent"))?,
+    )?;
+
+    steps()?;
+
+    remove_log(&log)
+}
+
+/// Finishes an operation that was interrupted after the image was moved, or rolls it back if
+/// the image never left its original location
+pub fn recover(output_dir: &Path) -> Result<(), Error> {
+    finish_interrupted(output_dir).map(|_| ())
+}
+
+/// Recovers like [`recover`], returning the interrupted operation if there was one, and whether
+/// it was finished rather than rolled back
+fn finish_interrupted(output_dir: &Path) -> Result<Option<(Intent, bool)>, Error> {
+    let log = output_dir.join(INTENT_LOG_NAME);
+    if !log.exists() {
+        return Ok(None);
+    }
+
+    let logged: Logged = std::fs::read_to_string(&log)
+        .map_err(Error::io(format!("Failed to read intent log {log:#?}")))
+        .and_then(|contents| {
+            toml::from_str(&contents).map_err(Error::format(format!("Invalid intent log {log:#?}")))
+        })?;
+    let intent = match logged {
+        Logged::Transfer(intent) => intent,
+        // Some files may have been moved already, so the merge can only go on
+        Logged::Merge(plan) => {
+            plan.finish()?;
+            remove_log(&log)?;
+            return Ok(None);
+        }
+    };
+    let entry = &intent.entry;
+
+    // The input is left untouched by the other modes, so their steps can simply be repeated
+    if entry.mode != TransferMode::Move {
+        if intent.revert {
+            if let Some(destination) = &entry.destination {
+                entry.restore_xmp(destination)?;
+            }
+            entry.revert_transfer()?;
+            entry.remove_captions()?;
+        } else {
+            if let Some(destination) = &entry.destination {
+                fsops::remove_leftovers(destination)?;
+                if !fsops::exists(destination) {
+                    entry.mode.transfer(&entry.source, destination)?;
+                }
+            }
+            entry.write_captions()?;
+            entry.finish_xmp()?;
+        }
+
+        remove_log(&log)?;
+        return Ok(Some((intent, true)));
+    }
+
+    // Where the image was coming from and going to, if it was moved at all
+    let paths = entry.destination.as_ref().map(|destination| {
+        if intent.revert {
+            (destination.as_path(), entry.source.as_path())
+        } else {
+            (entry.source.as_path(), destination.as_path())
+        }
+    });
+
+    let moved = match paths {
+        None => true,
+        Some((from, to)) => match (fsops::exists(from), fsops::exists(to)) {
+            (false, true) => true,
+            // The copy fallback finished, but the original wasn't removed yet
+            (true, true) => same_contents(from, to)?,
+            (true, false) => false,
+            (false, false) => {
+                return Err(Error::Unrecoverable(format!(
+                    "Interrupted operation left neither {from:#?} nor {to:#?} behind. Remove {log:#?} after locating the image."
+                )));
+            }
+        },
+    };
+
+    if moved {
+        if let Some((from, to)) = paths {
+            if from.exists() {
+                std::fs::remove_file(from)
+                    .map_err(Error::io(format!("Failed to remove {from:#?}")))?;
+            }
+            fsops::remove_leftovers(to)?;
+        }
+
+        if intent.revert {
+            entry.remove_captions()?;
+            entry.restore_sidecars()?;
+            // The image is back in the input directory
+            entry.restore_xmp(&entry.source)?;
+        } else {
+            entry.write_captions()?;
+            entry.apply_sidecars()?;
+            entry.finish_xmp()?;
+        }
+    } else {
+        if let Some((_, to)) = paths {
+            fsops::remove_leftovers(to)?;
+        }
+        for caption in &entry.captions {
+            fsops::remove_leftovers(&caption.path)?;
+        }
+    }
+
+    remove_log(&log)?;
+    Ok(Some((intent, moved)))
+}
+
+fn remove_log(log: &Path) -> Result<(), Error> {
+    std::fs::remove_file(log).map_err(Error::io(format!("Failed to remove intent log {log:#?}")))
+}
+
+fn same_contents(a: &Path, b: &Path) -> Result<bool, Error> {
+    let read =
+        |path: &Path| std::fs::read(path).map_err(Error::io(format!("Failed to read {path:#?}")));
+
+    Ok(read(a)? == read(b)?)
+}
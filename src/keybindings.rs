@@ -0,0 +1,133 @@
+use std::fmt::Display;
+
+use iced::keyboard::{Key, Modifiers, key::Named};
+use serde::{Deserialize, Serialize};
+
+/// Keys used in the labeling view while the prompt input isn't focused
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyBindings {
+    /// The n-th character submits the n-th class
+    pub class_keys: String,
+    pub trash: char,
+    pub skip: char,
+    /// Submits the default class while typing a caption
+    #[serde(default)]
+    pub submit: SubmitShortcut,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            class_keys: "123456789".into(),
+            trash: 'x',
+            skip: 's',
+            submit: SubmitShortcut::default(),
+        }
+    }
+}
+
+/// Keys submitting the default class while typing a caption. Enter with any other modifiers
+/// inserts a newline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SubmitShortcut {
+    #[default]
+    Enter,
+    CtrlEnter,
+    ShiftEnter,
+}
+
+impl SubmitShortcut {
+    pub const ALL: [SubmitShortcut; 3] = [
+        SubmitShortcut::Enter,
+        SubmitShortcut::CtrlEnter,
+        SubmitShortcut::ShiftEnter,
+    ];
+
+    pub fn matches(&self, key: &Key, modifiers: Modifiers) -> bool {
+        if *key != Key::Named(Named::Enter) {
+            return false;
+        }
+
+        match self {
+            SubmitShortcut::Enter => !modifiers.shift() && !modifiers.command(),
+            SubmitShortcut::CtrlEnter => modifiers.command(),
+            SubmitShortcut::ShiftEnter => modifiers.shift(),
+        }
+    }
+
+    /// Keys inserting a newline instead
+    pub fn newline(&self) -> &'static str {
+        match self {
+            SubmitShortcut::Enter => "Shift+Enter",
+            SubmitShortcut::CtrlEnter | SubmitShortcut::ShiftEnter => "Enter",
+        }
+    }
+}
+
+impl Display for SubmitShortcut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SubmitShortcut::Enter => "Enter",
+            SubmitShortcut::CtrlEnter => "Ctrl+Enter",
+            SubmitShortcut::ShiftEnter => "Shift+Enter",
+        })
+    }
+}
+
+impl KeyBindings {
+    pub fn class_key(&self, class_index: usize) -> Option<char> {
+        self.class_keys.chars().nth(class_index)
+    }
+
+    /// Index of the class bound to `key`
+    pub fn class_for(&self, key: &Key) -> Option<usize> {
+        self.class_keys.chars().position(|c| matches(key, c))
+    }
+
+    pub fn is_trash(&self, key: &Key) -> bool {
+        matches(key, self.trash)
+    }
+
+    pub fn is_skip(&self, key: &Key) -> bool {
+        matches(key, self.skip)
+    }
+}
+
+fn matches(key: &Key, binding: char) -> bool {
+    match key.as_ref() {
+        Key::Character(c) => {
+            let mut chars = c.chars();
+            chars
+                .next()
+                .is_some_and(|c| c.eq_ignore_ascii_case(&binding))
+                && chars.next().is_none()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn submit_shortcut_leaves_other_enters_for_newlines() {
+        let enter = Key::Named(Named::Enter);
+        let cases = [
+            (SubmitShortcut::Enter, Modifiers::empty(), true),
+            (SubmitShortcut::Enter, Modifiers::SHIFT, false),
+            (SubmitShortcut::CtrlEnter, Modifiers::COMMAND, true),
+            (SubmitShortcut::CtrlEnter, Modifiers::empty(), false),
+            (SubmitShortcut::ShiftEnter, Modifiers::SHIFT, true),
+            (SubmitShortcut::ShiftEnter, Modifiers::empty(), false),
+        ];
+        for (shortcut, modifiers, submits) in cases {
+            assert_eq!(
+                shortcut.matches(&enter, modifiers),
+                submits,
+                "{shortcut} with {modifiers:?}"
+            );
+        }
+        assert!(!SubmitShortcut::Enter.matches(&Key::Character("a".into()), Modifiers::empty()));
+    }
+}
@@ -0,0 +1,45 @@
+//! Headless core of quicklabel: indexing the input directory, the labeling queue and moving
+//! images and their captions into a kohya_ss (dreambooth-style) dataset.
+//!
+//! ```no_run
+//! use quicklabel::{Class, Dataset, LabelQueue, collision::CollisionPolicy};
+//!
+//! let mut dataset = Dataset::new("unsorted", "dataset");
+//! dataset.classes.push(Class::new("dog", 10));
+//! // Taken file names get a suffix instead of a question, so every image gets labeled
+//! dataset.collision_policy = CollisionPolicy::AutoSuffix;
+//! dataset.prepare()?;
+//!
+//! let mut queue = LabelQueue::new(dataset.index()?, Default::default());
+//! while let Some(index) = queue.next_queued() {
+//!     let image = queue.select(index).to_path_buf();
+//!     if let Some(entry) = dataset.label(&image, &dataset.classes[0], "a photo of a dog")? {
+//!         queue.record_applied(&entry);
+//!     }
+//! }
+//! # Ok::<(), quicklabel::Error>(())
+//! ```
+
+pub mod collision;
+pub mod dataset;
+pub mod error;
+pub mod fsops;
+pub mod journal;
+pub mod metadata;
+pub mod queue;
+pub mod scan;
+pub mod tags;
+pub mod template;
+pub mod tokens;
+pub mod xmp;
+
+pub use dataset::{
+    CLASS_PLACEHOLDER, CaptionOutput, CaptionOutputError, Class, ClassError, Dataset,
+    IMAGE_EXTENSIONS, TRIGGER_PLACEHOLDER,
+};
+pub use error::Error;
+pub use journal::JournalEntry;
+pub use metadata::Generation;
+pub use queue::{ImageStatus, LabelQueue, Progress};
+pub use scan::{ClassDir, Problem, Scan};
+pub use template::{Template, TemplateError};
@@ -1,45 +1,108 @@
 #![windows_subsystem = "windows"]
 
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
 
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use cli::{Cli, LaunchArgs};
 use iced::{
-    Element, Font, Task,
+    Element, Font, Subscription, Task,
     font::Weight,
     widget::{button, column, container, horizontal_space, row, text},
 };
+use keybindings::KeyBindings;
+use project::Project;
+use quicklabel::{Dataset, Progress, Template, TemplateError, fsops::TransferMode, tags::TagStyle};
+use serde::{Deserialize, Serialize};
 use views::{
     labeling::{LabelingMessage, LabelingState},
-    options::{OptionsMessage, OptionsState},
-    setup::{SetupMessage, SetupState},
+    options::{InputKind, OptionsMessage, OptionsState},
+    setup::{DirectoryKind, SetupMessage, SetupState},
 };
 
+mod cli;
+mod keybindings;
+mod project;
 mod views;
 
-#[derive(Debug, Clone, Default)]
-struct SharedState {
-    /// Dumping directory with images
-    input_dir: PathBuf,
-    /// Root directory of dreambooth-style dataset
-    output_dir: PathBuf,
-    /// If None, trashed images will be left in input_dir
-    trash_dir: Option<PathBuf>,
-
-    classes: Vec<Class>,
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SharedState {
+    #[serde(flatten)]
+    dataset: Dataset,
+    /// Template of the prompt pre-filled for every image
     prompt_prefill: Option<String>,
+    /// Regex matched against file names, whose groups can be used in the prefill
+    #[serde(default)]
+    filename_pattern: Option<String>,
+    /// Put the pre-filled prompt in front of sidecar captions and generation prompts, rather than
+    /// replacing it with them
+    #[serde(default)]
+    merge_sidecars: bool,
+    /// Pre-fill the prompt a generated image was made with, if there is no sidecar caption
+    #[serde(default)]
+    generation_prefill: bool,
+    /// Remove LoRA tags and weights from generation prompts before using them
+    #[serde(default)]
+    strip_prompt_syntax: bool,
+    /// Pre-fill the description and keywords of XMP metadata, if there is no sidecar caption
+    #[serde(default)]
+    xmp_prefill: bool,
+    /// Leave trigger words to the prompt instead of inserting them, only warning when one is
+    /// missing
+    #[serde(default)]
+    manual_triggers: bool,
+    /// Edit captions as removable comma-separated tags, like booru tags, rather than free text
+    #[serde(default)]
+    tag_mode: bool,
+    /// How words of the tags are separated
+    #[serde(default)]
+    tag_style: TagStyle,
+    /// CSV of tags to complete from, besides those already in the dataset
+    #[serde(default)]
+    tag_list: Option<PathBuf>,
+    /// Class submitted with Enter
+    #[serde(default)]
+    default_class: Option<usize>,
+    #[serde(default)]
+    key_bindings: KeyBindings,
 }
 
-impl Into<SharedState> for SetupState {
-    fn into(self) -> SharedState {
+impl SharedState {
+    /// Template of the pre-filled prompt, if there is one
+    fn prefill_template(&self) -> Result<Option<Template>, TemplateError> {
+        self.prompt_prefill
+            .as_deref()
+            .map(|prefill| Template::parse(prefill, self.filename_pattern.as_deref()))
+            .transpose()
+    }
+}
+
+impl From<SetupState> for SharedState {
+    fn from(setup: SetupState) -> Self {
         SharedState {
-            input_dir: self
-                .input_dir
-                .expect("Unreachable due to on_press_maybe condition"),
-            output_dir: self
-                .output_dir
-                .expect("Unreachable due to on_press_maybe condition"),
-            trash_dir: self.trash_dir,
-            classes: Vec::new(),
+            dataset: Dataset {
+                trash_dir: setup.trash_dir,
+                transfer_mode: setup.transfer_mode,
+                ..Dataset::new(
+                    setup
+                        .input_dir
+                        .expect("Unreachable due to on_press_maybe condition"),
+                    setup
+                        .output_dir
+                        .expect("Unreachable due to on_press_maybe condition"),
+                )
+            },
             prompt_prefill: None,
+            filename_pattern: None,
+            merge_sidecars: false,
+            generation_prefill: false,
+            strip_prompt_syntax: false,
+            xmp_prefill: false,
+            manual_triggers: false,
+            tag_mode: false,
+            tag_style: TagStyle::default(),
+            tag_list: None,
+            default_class: None,
+            key_bindings: KeyBindings::default(),
         }
     }
 }
@@ -50,28 +113,19 @@ pub enum Message {
     ResetState,
     Setup(SetupMessage),
     SetupDone(SetupState),
+    ResumeProject(Project),
     Options(OptionsMessage),
     GoOptions,
     GoLabel,
     Labeling(LabelingMessage),
     ShowText(String, String),
     FatalError(String),
+    /// Result of a task started during the given view generation
+    Stamped(u64, Box<Message>),
 }
 
-#[derive(Debug, Clone)]
-struct Class {
-    label: String,
-    repeats: usize,
-}
-
-impl Class {
-    fn path(&self, mut output_root: PathBuf) -> PathBuf {
-        output_root.push(format!("{}_{}", self.repeats, self.label));
-
-        output_root
-    }
-}
-
+// There is only ever one View, so its size doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 enum View {
     Setup(SetupState),
@@ -86,15 +140,140 @@ impl Default for View {
             input_dir: None,
             output_dir: None,
             trash_dir: None,
+            transfer_mode: TransferMode::default(),
+            last_session: project::last_session(),
+            error: None,
         })
     }
 }
 
+/// Top-level application state
+#[derive(Debug, Default)]
+struct QuickLabel {
+    view: View,
+    /// Incremented whenever the view changes, so results of tasks started by an earlier view can
+    /// be told apart and dropped
+    generation: u64,
+}
+
+impl QuickLabel {
+    /// Starts with whatever was passed on the command line filled in, skipping the views that
+    /// have nothing left to ask
+    fn launch(args: LaunchArgs) -> Result<(Self, Task<Message>), String> {
+        let mut app = Self::default();
+
+        if let Some(path) = args.project {
+            let project = Project::load(&path).map_err(|e| e.to_string())?;
+            let task = app.update(Message::ResumeProject(project));
+
+            return Ok((app, task));
+        }
+
+        // Follow-up work of every step, like scans and saves, still has to run once the GUI is up
+        let mut tasks = Vec::new();
+
+        let directories = [
+            (DirectoryKind::Input, args.input),
+            (DirectoryKind::Output, args.output),
+            (DirectoryKind::Trash, args.trash),
+        ];
+        for (kind, path) in directories {
+            if let Some(path) = path {
+                tasks.push(app.update(SetupMessage::SetPath(kind, path).into()));
+            }
+        }
+
+        let View::Setup(setup) = &app.view else {
+            return Ok((app, Task::batch(tasks)));
+        };
+        if setup.input_dir.is_none() || setup.output_dir.is_none() {
+            return Ok((app, Task::batch(tasks)));
+        }
+        tasks.push(app.update(Message::SetupDone(setup.clone())));
+
+        let labeling = !args.classes.is_empty();
+        for class in args.classes {
+            // Already picked up from the output directory
+            if let View::Options(shared, ..) = &app.view
+                && shared
+                    .dataset
+                    .classes
+                    .iter()
+                    .any(|known| known.same_dir(&class))
+            {
+                continue;
+            }
+
+            tasks.push(
+                app.update(
+                    OptionsMessage::AddClass((
+                        class.label,
+                        class.repeats,
+                        class.trigger.unwrap_or_default(),
+                    ))
+                    .into(),
+                ),
+            );
+        }
+        if let Some(prefill) = args.prefill {
+            tasks.push(
+                app.update(OptionsMessage::InputChange((InputKind::PromptPrefill, prefill)).into()),
+            );
+        }
+
+        if labeling {
+            tasks.push(app.update(Message::GoLabel));
+        }
+
+        Ok((app, Task::batch(tasks)))
+    }
+
+    pub fn update(&mut self, message: Message) -> Task<Message> {
+        if let Message::Stamped(generation, message) = message {
+            if generation != self.generation {
+                // The files were already moved, so the session has to know even though the view
+                // that asked for it is gone
+                if let Message::Labeling(
+                    message @ (LabelingMessage::Applied(..) | LabelingMessage::Reverted(..)),
+                ) = *message
+                {
+                    return self.view.record_stale(message);
+                }
+
+                return Task::none();
+            }
+
+            return self.update(*message);
+        }
+
+        let before = std::mem::discriminant(&self.view);
+        let task = self.view.update(message);
+        if std::mem::discriminant(&self.view) != before {
+            self.generation += 1;
+        }
+
+        let generation = self.generation;
+        task.map(move |message| Message::Stamped(generation, Box::new(message)))
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        self.view.view()
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        self.view.subscription()
+    }
+}
+
 impl View {
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::NoOp => {}
 
+            Message::Stamped(_, message) => {
+                return self.update(*message);
+            }
+
             Message::ResetState => {
                 *self = View::default();
             }
@@ -111,55 +290,99 @@ impl View {
                 if let View::Setup(setup) = self {
                     return views::setup::update(setup, message);
                 } else {
-                    panic!("Setup Message sent by other view?! -- {:#?}", self);
+                    self.drop_misrouted(message);
                 }
             }
 
             Message::SetupDone(setup) => {
-                *self = View::Options(setup.into(), OptionsState::default());
+                if let View::Setup(..) = self {
+                    let mut shared = SharedState::from(setup);
+
+                    // Pick up the classes that are already in the output root
+                    let existing = shared.dataset.existing_classes();
+                    if let Ok(classes) = &existing {
+                        shared.dataset.classes = classes.clone();
+                    }
+
+                    let (mut options, scan) = OptionsState::new(&shared);
+                    if let Err(e) = existing {
+                        options.error = Some(e.to_string());
+                    }
+                    *self = View::Options(shared, options);
+
+                    return scan;
+                } else {
+                    self.drop_misrouted("SetupDone");
+                }
+            }
+
+            Message::ResumeProject(project) => {
+                if let View::Setup(..) = self {
+                    let (options, scan) = OptionsState::new(&project.session);
+                    *self = View::Options(project.session, options);
+
+                    let labeling = self.begin_labeling(project.progress);
+                    // The scan is only of use if labeling couldn't begin
+                    return if let View::Options(..) = self {
+                        Task::batch([scan, labeling])
+                    } else {
+                        labeling
+                    };
+                } else {
+                    self.drop_misrouted("ResumeProject");
+                }
             }
 
             Message::Options(message) => {
                 if let View::Options(shared, local) = self {
                     return views::options::update(shared, local, message);
                 } else {
-                    panic!("Options Message sent by other view?! -- {:#?}", self);
+                    self.drop_misrouted(message);
                 }
             }
 
             Message::GoOptions => {
-                if let View::Labeling(shared, ..) = self {
-                    *self = View::Options(std::mem::take(shared), OptionsState::default());
+                if let View::Labeling(shared, local) = self {
+                    // The labeling state is gone once the view is left
+                    let saved = views::labeling::save_pending(shared, local);
+                    let (mut options, scan) = OptionsState::new(shared);
+                    if let Err(e) = saved {
+                        options.error = Some(format!("The progress can't be saved: {e}"));
+                    }
+                    *self = View::Options(std::mem::take(shared), options);
+
+                    return scan;
                 } else {
-                    panic!("GoOptions from other view?! -- {:#?}", self);
+                    self.drop_misrouted("GoOptions");
                 }
             }
 
             Message::GoLabel => {
-                if let View::Options(shared, ..) = self {
-                    // Ensure all directories exist before proceeding
-                    for class in &shared.classes {
-                        let class_dir = class.path(shared.output_dir.clone());
-                        if let Err(e) = std::fs::create_dir_all(&class_dir) {
-                            return Task::done(Message::FatalError(format!(
-                                "Unable to create directory {class_dir:#?}: {e}"
-                            )));
-                        }
+                if let View::Options(shared, options) = self {
+                    // The classes are about to change
+                    if options.moving {
+                        return Task::none();
                     }
 
-                    *self = View::Labeling(std::mem::take(shared), LabelingState::default());
+                    // Continue where the project file left off, if there is one
+                    let progress = Project::load(&Project::path(&shared.dataset.output_dir))
+                        .map(|project| project.progress)
+                        .unwrap_or_default();
 
-                    return Task::done(LabelingMessage::Index.into());
+                    return self.begin_labeling(progress);
                 } else {
-                    panic!("GoLabel from other view?! -- {:#?}", self);
+                    self.drop_misrouted("GoLabel");
                 }
             }
 
             Message::Labeling(message) => {
                 if let View::Labeling(shared, local) = self {
                     return views::labeling::update(shared, local, message);
+                } else if let LabelingMessage::Applied(..) | LabelingMessage::Reverted(..) = message
+                {
+                    return self.record_stale(message);
                 } else {
-                    panic!("Labeling Message sent by other view?! -- {:#?}", self);
+                    self.drop_misrouted(message);
                 }
             }
         }
@@ -167,7 +390,96 @@ impl View {
         Task::none()
     }
 
-    pub fn view(&self) -> Element<Message> {
+    /// Messages meant for another view can arrive after the view changed, e.g. from key presses
+    /// queued just before. They are meaningless now, so they are ignored.
+    fn drop_misrouted(&self, message: impl std::fmt::Debug) {
+        #[cfg(debug_assertions)]
+        eprintln!(
+            "Dropping {message:?}, which isn't meant for the {} view",
+            self.title()
+        );
+        #[cfg(not(debug_assertions))]
+        let _ = message;
+    }
+
+    /// Records an `Applied` or `Reverted` result of a file operation that finished after the
+    /// labeling view it was started in was left
+    fn record_stale(&mut self, message: LabelingMessage) -> Task<Message> {
+        match self {
+            View::Labeling(shared, local) => views::labeling::record_stale(shared, local, message),
+            View::Options(shared, local) => {
+                let path = Project::path(&shared.dataset.output_dir);
+                let mut progress = Project::load(&path)
+                    .map(|project| project.progress)
+                    .unwrap_or_default();
+                match &message {
+                    LabelingMessage::Applied(entry) => progress.record_applied(entry),
+                    LabelingMessage::Reverted(entry) => progress.record_reverted(entry),
+                    _ => return Task::none(),
+                }
+
+                let project = Project {
+                    session: shared.clone(),
+                    progress,
+                };
+                if let Err(e) = project.save() {
+                    local.error = Some(e.to_string());
+                }
+
+                Task::none()
+            }
+            // The session is over, and labeling it again starts from the input directory as it is
+            View::Setup(..) | View::FatalError(..) => {
+                self.drop_misrouted(message);
+                Task::none()
+            }
+        }
+    }
+
+    /// Switches from the options view into labeling, resuming from `progress`
+    fn begin_labeling(&mut self, progress: Progress) -> Task<Message> {
+        let View::Options(shared, local) = self else {
+            self.drop_misrouted("Request to begin labeling");
+            return Task::none();
+        };
+
+        let template = match shared.prefill_template() {
+            Ok(template) => template,
+            Err(e) => {
+                local.error = Some(format!("Pre-filled prompt: {e}"));
+                return Task::none();
+            }
+        };
+
+        // Finish whatever was interrupted last time before indexing the input directory, and
+        // ensure all directories exist before proceeding
+        let prepared = shared.dataset.prepare().and_then(|()| {
+            Project {
+                session: shared.clone(),
+                progress: progress.clone(),
+            }
+            .save()
+        });
+
+        match prepared {
+            Ok(()) => {}
+            // Stay in the options, so the user can fix the problem and try again
+            Err(e) if e.is_recoverable() => {
+                local.error = Some(e.to_string());
+                return Task::none();
+            }
+            Err(e) => return Task::done(Message::FatalError(e.to_string())),
+        }
+
+        *self = View::Labeling(
+            std::mem::take(shared),
+            LabelingState::resume(progress, template),
+        );
+
+        Task::done(LabelingMessage::Index.into())
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
         column![
             row![
                 text(self.title()).font(Font {
@@ -196,31 +508,270 @@ impl View {
         .into()
     }
 
+    pub fn subscription(&self) -> Subscription<Message> {
+        match self {
+            View::Labeling(..) => views::labeling::subscription(),
+            _ => Subscription::none(),
+        }
+    }
+
     fn title(&self) -> &str {
         match self {
             View::Setup(..) => "Directories",
             View::Options(..) => "Options",
-            View::FatalError(title, ..) => {
-                if let Some(title) = title {
-                    title
-                } else {
-                    "Fatal Error"
-                }
-            }
+            View::FatalError(title, ..) => title.as_deref().unwrap_or("Fatal Error"),
             View::Labeling(..) => "Label",
         }
     }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    // Subcommands, help and argument errors are written to the terminal quicklabel was run from
+    if std::env::args_os().len() > 1 {
+        attach_console();
+    }
+
+    let cli = Cli::parse();
+    if let Some(command) = cli.command {
+        return cli::run(command);
+    }
+
+    let invalid = |message: String| Cli::command().error(ErrorKind::ValueValidation, message);
+    if let Err(message) = cli.launch.validate() {
+        invalid(message).exit();
+    }
+    let (app, task) =
+        QuickLabel::launch(cli.launch).unwrap_or_else(|message| invalid(message).exit());
+
     println!(
         "quicklabel v{}; https://github.com/sysrqmagician/quicklabel\nCopyright (c) 2025, sysrqmagician <sysrqmagician@proton.me>\n",
         env!("CARGO_PKG_VERSION")
     );
     println!(include_str!("../LICENSE"));
 
-    iced::application("quicklabel", View::update, View::view)
-        .run()
+    iced::application("quicklabel", QuickLabel::update, QuickLabel::view)
+        .subscription(QuickLabel::subscription)
+        .run_with(move || (app, task))
         .expect("Failed to run GUI");
+
+    ExitCode::SUCCESS
+}
+
+/// Connects stdout and stderr to the console of the parent process, if it has one. The GUI
+/// subsystem starts without a console, so the output would go nowhere otherwise.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    unsafe extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // Fails when started from Explorer, which is fine as nobody would read the output there
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::views::setup::DirectoryKind;
+
+    fn labeling() -> QuickLabel {
+        QuickLabel {
+            view: View::Labeling(SharedState::default(), LabelingState::default()),
+            generation: 5,
+        }
+    }
+
+    #[test]
+    fn stale_finish_indexing_is_dropped() {
+        let mut app = labeling();
+        let _ = app.update(Message::GoOptions);
+        assert_eq!(app.generation, 6);
+
+        let _ = app.update(Message::Stamped(
+            5,
+            Box::new(LabelingMessage::FinishIndexing(vec![PathBuf::from("a.png")]).into()),
+        ));
+        assert!(matches!(app.view, View::Options(..)));
+    }
+
+    #[test]
+    fn stale_next_image_is_dropped() {
+        let mut app = labeling();
+        let _ = app.update(Message::GoOptions);
+
+        let _ = app.update(Message::Stamped(
+            5,
+            Box::new(LabelingMessage::NextImage.into()),
+        ));
+        assert!(matches!(app.view, View::Options(..)));
+    }
+
+    #[test]
+    fn stale_applied_is_recorded_in_the_project() {
+        let root = tempfile::tempdir().unwrap();
+        let input = root.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::fs::write(input.join("a.png"), "").unwrap();
+
+        let mut shared = SharedState {
+            dataset: Dataset {
+                transfer_mode: TransferMode::Copy,
+                ..Dataset::new(&input, root.path().join("output"))
+            },
+            ..Default::default()
+        };
+        shared
+            .dataset
+            .classes
+            .push(quicklabel::Class::new("dog", 10));
+        shared.dataset.prepare().unwrap();
+        let entry = shared
+            .dataset
+            .label(&input.join("a.png"), &shared.dataset.classes[0], "a dog")
+            .unwrap()
+            .unwrap();
+
+        let mut app = QuickLabel {
+            view: View::Labeling(shared, LabelingState::default()),
+            generation: 5,
+        };
+        let _ = app.update(Message::GoOptions);
+        let _ = app.update(Message::Stamped(
+            5,
+            Box::new(LabelingMessage::Applied(entry).into()),
+        ));
+
+        // The copy is still in the input directory, but mustn't be labeled again
+        let project = Project::load(&Project::path(&root.path().join("output"))).unwrap();
+        assert_eq!(project.progress.left_in_place, [input.join("a.png")]);
+        assert_eq!(project.progress.labeled, 1);
+    }
+
+    #[test]
+    fn cli_labels_take_images_out_of_the_deferred_ones() {
+        let root = tempfile::tempdir().unwrap();
+        let (input, output) = (root.path().join("input"), root.path().join("output"));
+        std::fs::create_dir(&input).unwrap();
+        std::fs::create_dir(&output).unwrap();
+        std::fs::write(input.join("a.png"), "").unwrap();
+
+        let mut project = Project {
+            session: SharedState {
+                dataset: Dataset::new(&input, &output),
+                ..Default::default()
+            },
+            progress: Default::default(),
+        };
+        project.progress.deferred.push(input.join("a.png"));
+        std::fs::write(Project::path(&output), toml::to_string(&project).unwrap()).unwrap();
+
+        let cli = Cli::try_parse_from([
+            "quicklabel",
+            "label",
+            "--input",
+            input.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--class",
+            "10_dog",
+            "--caption",
+            "a dog",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli::run(cli.command.unwrap()),
+            std::process::ExitCode::SUCCESS
+        );
+
+        let progress = Project::load(&Project::path(&output)).unwrap().progress;
+        assert!(progress.deferred.is_empty());
+        assert_eq!(progress.labeled, 1);
+    }
+
+    #[test]
+    fn misrouted_messages_do_not_panic() {
+        let mut app = QuickLabel::default();
+
+        let _ = app.update(LabelingMessage::NextImage.into());
+        let _ = app.update(LabelingMessage::FinishIndexing(Vec::new()).into());
+        let _ = app.update(Message::GoOptions);
+        let _ = app.update(Message::GoLabel);
+        assert!(matches!(app.view, View::Setup(..)));
+        assert_eq!(app.generation, 0);
+    }
+
+    #[test]
+    fn launch_skips_to_options() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("10_dog")).unwrap();
+        std::fs::write(root.path().join("10_dog").join("a.png"), "").unwrap();
+        let cli = Cli::try_parse_from([
+            "quicklabel",
+            "--input",
+            root.path().to_str().unwrap(),
+            "--output",
+            root.path().to_str().unwrap(),
+            "--prefill",
+            "a photo of",
+        ])
+        .unwrap();
+        cli.launch.validate().unwrap();
+
+        let (mut app, _) = QuickLabel::launch(cli.launch).unwrap();
+        let View::Options(shared, options) = &app.view else {
+            panic!("Didn't skip to options?!");
+        };
+        assert_eq!(shared.dataset.input_dir, root.path());
+        assert_eq!(shared.prompt_prefill.as_deref(), Some("a photo of"));
+
+        // Existing class directories are picked up, and their images counted in the background
+        assert_eq!(shared.dataset.classes, [quicklabel::Class::new("dog", 10)]);
+        assert!(options.scanning);
+
+        let scan = shared.dataset.scan();
+        let _ = app.update(OptionsMessage::Scanned(0, Ok(Default::default()), None).into());
+        let _ = app.update(OptionsMessage::Scanned(1, scan, None).into());
+        let View::Options(_, options) = &app.view else {
+            panic!("Left options?!");
+        };
+        assert!(!options.scanning);
+        assert_eq!(options.existing.images(), 1);
+    }
+
+    #[test]
+    fn launch_args_are_validated() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["quicklabel"], args].concat());
+
+        assert!(parse(&["--class", "10:dog"]).is_err());
+        assert!(parse(&["--project", "a.toml", "--input", "in"]).is_err());
+        assert!(parse(&["--input", "in", "--output", "out", "--class", "dog"]).is_err());
+
+        let cli = parse(&["--input", "in", "--output", "out", "--class", "10:dog"]).unwrap();
+        assert_eq!(cli.launch.classes, [quicklabel::Class::new("dog", 10)]);
+        assert!(cli.launch.validate().is_err());
+    }
+
+    #[test]
+    fn current_generation_is_delivered() {
+        let mut app = QuickLabel::default();
+
+        let _ = app.update(Message::Stamped(
+            0,
+            Box::new(SetupMessage::SetPath(DirectoryKind::Input, PathBuf::from("in")).into()),
+        ));
+        let View::Setup(setup) = &app.view else {
+            panic!("Left setup?!");
+        };
+        assert_eq!(setup.input_dir, Some(PathBuf::from("in")));
+    }
 }
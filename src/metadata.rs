@@ -0,0 +1,371 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::error::Error;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// How a generated image was made, as recorded by the tool that generated it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Generation {
+    /// Positive prompt
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub seed: Option<String>,
+    pub model: Option<String>,
+    /// Everything else that was recorded, like steps or sampler, in the recorded order
+    pub settings: Vec<(String, String)>,
+}
+
+impl Generation {
+    /// Generation metadata of `image`, from the text chunks of a PNG or the EXIF user comment of
+    /// a JPEG. None if there isn't any that is understood.
+    pub fn read(image: &Path) -> Result<Option<Self>, Error> {
+        let mut file = BufReader::new(
+            File::open(image).map_err(Error::io(format!("Failed to open {image:#?}")))?,
+        );
+
+        let mut signature = [0; PNG_SIGNATURE.len()];
+        let is_png = file.read_exact(&mut signature).is_ok() && signature == PNG_SIGNATURE;
+        let texts = if is_png {
+            png_texts(&mut file)
+                .map_err(Error::io(format!("Failed to read metadata of {image:#?}")))?
+        } else {
+            // Cameras and editors leave comments too, only take those with generation settings
+            user_comment(image)
+                .filter(|comment| comment.contains("Steps: "))
+                .map(|comment| vec![("parameters".to_string(), comment)])
+                .unwrap_or_default()
+        };
+
+        let text = |keyword: &str| {
+            texts
+                .iter()
+                .find(|(key, _)| key == keyword)
+                .map(|(_, text)| text.as_str())
+        };
+
+        Ok(text("parameters")
+            .and_then(Self::from_parameters)
+            .or_else(|| text("prompt").and_then(Self::from_comfy_prompt))
+            .or_else(|| text("workflow").and_then(Self::from_comfy_workflow)))
+    }
+
+    /// Parses the `parameters` written by AUTOMATIC1111 and its forks:
+    ///
+    /// ```text
+    /// a photo of a dog
+    /// Negative prompt: blurry
+    /// Steps: 20, Sampler: Euler a, Seed: 1234, Model: sd_xl_base_1.0
+    /// ```
+    pub fn from_parameters(parameters: &str) -> Option<Self> {
+        static SETTING: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"\s*([\w \-/]+):\s*("(?:\\.|[^\\"])+"|[^,]*)(?:,|$)"#).unwrap()
+        });
+
+        let mut lines: Vec<&str> = parameters.trim().lines().collect();
+        let mut settings = Vec::new();
+        if let Some(last) = lines.last()
+            && last.starts_with("Steps: ")
+        {
+            settings = SETTING
+                .captures_iter(last)
+                .map(|captures| {
+                    (
+                        captures[1].trim().to_string(),
+                        captures[2].trim().trim_matches('"').to_string(),
+                    )
+                })
+                .collect();
+            lines.pop();
+        }
+
+        let negative_start = lines
+            .iter()
+            .position(|line| line.starts_with("Negative prompt:"));
+        let (prompt, negative_prompt) = match negative_start {
+            Some(start) => {
+                let negative = lines[start..].join("\n");
+                let negative = negative["Negative prompt:".len()..].trim().to_string();
+                (lines[..start].join("\n"), Some(negative))
+            }
+            None => (lines.join("\n"), None),
+        };
+
+        if prompt.trim().is_empty() && settings.is_empty() {
+            return None;
+        }
+
+        let mut take = |name: &str| {
+            let index = settings.iter().position(|(key, _)| key == name)?;
+            Some(settings.remove(index).1)
+        };
+
+        Some(Self {
+            prompt: prompt.trim().to_string(),
+            negative_prompt: negative_prompt.filter(|negative| !negative.is_empty()),
+            seed: take("Seed"),
+            model: take("Model").or_else(|| take("Model hash")),
+            settings,
+        })
+    }
+
+    /// Parses the API format graph ComfyUI stores as `prompt`, following the positive and
+    /// negative inputs of the sampler to their text encoders
+    pub fn from_comfy_prompt(prompt: &str) -> Option<Self> {
+        let graph: serde_json::Map<String, Value> = serde_json::from_str(prompt).ok()?;
+
+        // A link is [node id, output index]
+        let follow = |input: &Value| -> Option<String> {
+            let mut input = input.clone();
+            for _ in 0..graph.len() {
+                match input {
+                    Value::String(text) => return Some(text),
+                    Value::Array(link) => {
+                        let node = &graph.get(link.first()?.as_str()?)?["inputs"];
+                        input = ["text", "text_g", "string", "value"]
+                            .iter()
+                            .find_map(|name| node.get(name))?
+                            .clone();
+                    }
+                    _ => return None,
+                }
+            }
+            None
+        };
+
+        let sampler = graph
+            .values()
+            .map(|node| &node["inputs"])
+            .find(|inputs| inputs.get("positive").is_some())?;
+        let prompt = follow(&sampler["positive"])?;
+        let seed = ["seed", "noise_seed"]
+            .iter()
+            .find_map(|name| sampler.get(name))
+            .filter(|seed| seed.is_number())
+            .map(Value::to_string);
+        let model = graph
+            .values()
+            .find_map(|node| node["inputs"].get("ckpt_name")?.as_str())
+            .map(str::to_string);
+
+        let settings = ["steps", "cfg", "sampler_name", "scheduler", "denoise"]
+            .iter()
+            .filter_map(|name| {
+                let value = sampler.get(name)?;
+                let value = value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), str::to_string);
+                Some((name.to_string(), value))
+            })
+            .collect();
+
+        Some(Self {
+            prompt,
+            negative_prompt: sampler.get("negative").and_then(follow),
+            seed,
+            model,
+            settings,
+        })
+    }
+
+    /// Parses the UI format graph ComfyUI stores as `workflow`, for images that lack `prompt`
+    pub fn from_comfy_workflow(workflow: &str) -> Option<Self> {
+        let workflow: Value = serde_json::from_str(workflow).ok()?;
+        let nodes = workflow["nodes"].as_array()?;
+        // A link is [id, from node, from slot, to node, to slot, type]
+        let links = workflow["links"].as_array()?;
+
+        let node_by_id = |id: &Value| nodes.iter().find(|node| node["id"] == *id);
+        let text_of = |sampler: &Value, input: &str| -> Option<String> {
+            let link = sampler["inputs"]
+                .as_array()?
+                .iter()
+                .find(|slot| slot["name"] == input)?
+                .get("link")?;
+            let link = links.iter().find(|other| other[0] == *link)?;
+            let encoder = node_by_id(&link[1])?;
+
+            encoder["widgets_values"][0].as_str().map(str::to_string)
+        };
+
+        let sampler = nodes.iter().find(|node| {
+            node["inputs"]
+                .as_array()
+                .is_some_and(|inputs| inputs.iter().any(|slot| slot["name"] == "positive"))
+        })?;
+
+        Some(Self {
+            prompt: text_of(sampler, "positive")?,
+            negative_prompt: text_of(sampler, "negative"),
+            model: nodes
+                .iter()
+                .find(|node| node["type"] == "CheckpointLoaderSimple")
+                .and_then(|node| node["widgets_values"][0].as_str())
+                .map(str::to_string),
+            ..Default::default()
+        })
+    }
+}
+
+/// Removes LoRA tags like `<lora:name:0.8>` and weighting like `(word:1.2)` or `[word]`,
+/// leaving plain text for a caption
+pub fn strip_prompt_syntax(prompt: &str) -> String {
+    static NETWORK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[a-zA-Z]+:[^>]*>").unwrap());
+    static WEIGHT: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r":\s*-?[0-9]*\.?[0-9]+\s*\)").unwrap());
+    static SEPARATORS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*,[\s,]*").unwrap());
+    static SPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t]+").unwrap());
+
+    // Escaped brackets are literal, keep them out of the way while unwrapping the others
+    let escaped = [
+        ("\\(", '\u{E000}'),
+        ("\\)", '\u{E001}'),
+        ("\\[", '\u{E002}'),
+        ("\\]", '\u{E003}'),
+    ];
+    let mut prompt = prompt.to_string();
+    for (escape, stand_in) in escaped {
+        prompt = prompt.replace(escape, &stand_in.to_string());
+    }
+
+    let prompt = NETWORK.replace_all(&prompt, "");
+    let prompt = WEIGHT.replace_all(&prompt, ")");
+    let prompt: String = prompt
+        .chars()
+        .filter(|c| !matches!(c, '(' | ')' | '[' | ']'))
+        .map(|c| match c {
+            '\u{E000}' => '(',
+            '\u{E001}' => ')',
+            '\u{E002}' => '[',
+            '\u{E003}' => ']',
+            c => c,
+        })
+        .collect();
+
+    let prompt = SEPARATORS.replace_all(&prompt, ", ");
+    let prompt = SPACES.replace_all(&prompt, " ");
+
+    prompt
+        .trim_matches(|c: char| c == ',' || c.is_whitespace())
+        .to_string()
+}
+
+/// (keyword, text) of the `tEXt`, `zTXt` and `iTXt` chunks of a PNG, read after its signature
+fn png_texts(png: &mut impl Read) -> std::io::Result<Vec<(String, String)>> {
+    let mut texts = Vec::new();
+
+    loop {
+        let mut header = [0; 8];
+        if png.read_exact(&mut header).is_err() {
+            break;
+        }
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let kind = &header[4..];
+
+        if kind == b"IDAT" || kind == b"IEND" {
+            // Text chunks before the image data are all A1111 and ComfyUI write
+            break;
+        }
+
+        // The length is only trusted as far as the file goes
+        let mut data = Vec::new();
+        png.by_ref().take(length as u64).read_to_end(&mut data)?;
+        if data.len() < length {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        png.read_exact(&mut [0; 4])?;
+
+        let Some(separator) = data.iter().position(|byte| *byte == 0) else {
+            continue;
+        };
+        let keyword = latin1(&data[..separator]);
+        let rest = &data[separator + 1..];
+
+        let text = match kind {
+            b"tEXt" => Some(latin1(rest)),
+            b"zTXt" => rest.get(1..).and_then(inflate).map(|text| latin1(&text)),
+            b"iTXt" => international_text(rest),
+            _ => None,
+        };
+        if let Some(text) = text {
+            texts.push((keyword, text));
+        }
+    }
+
+    Ok(texts)
+}
+
+/// Text of an `iTXt` chunk after its keyword
+pub(crate) fn international_text(data: &[u8]) -> Option<String> {
+    let compressed = *data.first()? == 1;
+    // Skip the compression flag and method, then the language tag and translated keyword
+    let mut rest = data.get(2..)?;
+    for _ in 0..2 {
+        let end = rest.iter().position(|byte| *byte == 0)?;
+        rest = &rest[end + 1..];
+    }
+
+    let text = if compressed {
+        inflate(rest)?
+    } else {
+        rest.to_vec()
+    };
+
+    String::from_utf8(text).ok()
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .read_to_end(&mut inflated)
+        .ok()?;
+
+    Some(inflated)
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| char::from(*byte)).collect()
+}
+
+/// EXIF user comment of `image`, where A1111 stores the parameters of JPEGs
+fn user_comment(image: &Path) -> Option<String> {
+    let file = File::open(image).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::UserComment, exif::In::PRIMARY)?;
+    let exif::Value::Undefined(bytes, _) = &field.value else {
+        return None;
+    };
+
+    // The first 8 bytes name the encoding
+    let (encoding, text) = bytes.split_at_checked(8)?;
+    let comment = match encoding {
+        b"UNICODE\0" => {
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            // Writers disagree on the byte order, text in the wrong one is mostly CJK lookalikes
+            let little_endian = units.iter().filter(|unit| **unit & 0xff == 0).count()
+                > units.iter().filter(|unit| **unit >> 8 == 0).count();
+            let units: Vec<u16> = if little_endian {
+                units.iter().map(|unit| unit.swap_bytes()).collect()
+            } else {
+                units
+            };
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+
+    Some(comment.trim_end_matches('\0').to_string())
+}
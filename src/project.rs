@@ -0,0 +1,66 @@
+use std::path::{Path, PathBuf};
+
+use quicklabel::{Error, Progress, fsops};
+use serde::{Deserialize, Serialize};
+
+use crate::SharedState;
+
+/// Name of the project file stored in the output root
+pub const PROJECT_FILE_NAME: &str = "quicklabel.toml";
+
+/// A labeling session that can be written to disk and resumed later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub session: SharedState,
+    #[serde(default)]
+    pub progress: Progress,
+}
+
+impl Project {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(PROJECT_FILE_NAME)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(Error::io(format!("Failed to read project file {path:#?}")))?;
+
+        toml::from_str(&contents).map_err(Error::format(format!("Invalid project file {path:#?}")))
+    }
+
+    /// Writes the project into the output root and remembers it as the last session
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path(&self.session.dataset.output_dir);
+        let contents =
+            toml::to_string_pretty(self).map_err(Error::format("Failed to serialize project"))?;
+
+        fsops::write_atomic(&path, contents)?;
+
+        remember_last_session(&path);
+
+        Ok(())
+    }
+}
+
+fn last_session_record() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("quicklabel").join("last_session"))
+}
+
+/// Path of the most recently saved project, if it still exists
+pub fn last_session() -> Option<PathBuf> {
+    let path = PathBuf::from(std::fs::read_to_string(last_session_record()?).ok()?.trim());
+
+    path.is_file().then_some(path)
+}
+
+fn remember_last_session(project_path: &Path) {
+    let Some(record) = last_session_record() else {
+        return;
+    };
+
+    // Failing to remember the session only disables "Resume Last Session", so don't bother the user
+    if let Some(parent) = record.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let _ = std::fs::write(record, project_path.to_string_lossy().as_bytes());
+}
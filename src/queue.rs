@@ -0,0 +1,313 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::JournalEntry;
+
+/// Where a labeling session left off
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Progress {
+    /// Image the labeling cursor was on when the session was last saved
+    pub cursor: Option<PathBuf>,
+    /// Skipped images, revisited once the cursor reaches the end
+    pub deferred: Vec<PathBuf>,
+    /// Images that were dealt with, but remain in the input directory
+    pub left_in_place: Vec<PathBuf>,
+    pub labeled: usize,
+    pub trashed: usize,
+}
+
+impl Progress {
+    /// Records that `entry` was applied, for sessions that aren't loaded into a queue
+    pub fn record_applied(&mut self, entry: &JournalEntry) {
+        self.deferred.retain(|image| *image != entry.source);
+        if !entry.removes_source() && !self.left_in_place.contains(&entry.source) {
+            self.left_in_place.push(entry.source.clone());
+        }
+
+        if entry.trashed {
+            self.trashed += 1;
+        } else {
+            self.labeled += 1;
+        }
+    }
+
+    /// Records that `entry` was reverted, for sessions that aren't loaded into a queue
+    pub fn record_reverted(&mut self, entry: &JournalEntry) {
+        self.left_in_place.retain(|image| *image != entry.source);
+        if entry.deferred && !self.deferred.contains(&entry.source) {
+            self.deferred.push(entry.source.clone());
+        }
+
+        if entry.trashed {
+            self.trashed = self.trashed.saturating_sub(1);
+        } else {
+            self.labeled = self.labeled.saturating_sub(1);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageStatus {
+    Pending,
+    /// Skipped, to be revisited at the end
+    Deferred,
+    /// Labeled or trashed, and moved out of the input directory
+    Moved,
+    /// Labeled or trashed, but left in the input directory
+    LeftInPlace,
+}
+
+impl ImageStatus {
+    /// Whether the image still needs to be labeled
+    pub fn is_open(self) -> bool {
+        matches!(self, ImageStatus::Pending | ImageStatus::Deferred)
+    }
+}
+
+/// The indexed images and which of them still need to be labeled
+#[derive(Debug, Clone, Default)]
+pub struct LabelQueue {
+    images: Vec<PathBuf>,
+    /// Status of each image in `images`
+    status: Vec<ImageStatus>,
+    cursor: usize,
+    /// The current image was deferred, so the queue continues with the next deferred one
+    revisiting: bool,
+    /// Images labeled across all sessions
+    labeled: usize,
+    /// Images trashed across all sessions
+    trashed: usize,
+}
+
+impl LabelQueue {
+    /// Queue of the sorted `images`, continuing from `progress`.
+    ///
+    /// The cursor starts on the saved image, or whatever followed it. It may not be open, so
+    /// check [`LabelQueue::status`] before falling back to [`LabelQueue::next_queued`].
+    pub fn new(images: Vec<PathBuf>, progress: Progress) -> Self {
+        let status = images
+            .iter()
+            .map(|path| {
+                if progress.deferred.contains(path) {
+                    ImageStatus::Deferred
+                } else if progress.left_in_place.contains(path) {
+                    ImageStatus::LeftInPlace
+                } else {
+                    ImageStatus::Pending
+                }
+            })
+            .collect();
+
+        // Images are sorted, so this lands on the saved image or whatever followed it
+        let cursor = progress
+            .cursor
+            .map_or(0, |cursor| images.partition_point(|path| *path < cursor))
+            .min(images.len().saturating_sub(1));
+
+        Self {
+            images,
+            status,
+            cursor,
+            revisiting: false,
+            labeled: progress.labeled,
+            trashed: progress.trashed,
+        }
+    }
+
+    pub fn images(&self) -> &[PathBuf] {
+        &self.images
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn status(&self, index: usize) -> ImageStatus {
+        self.status[index]
+    }
+
+    /// Whether the image under the cursor came from the deferred images
+    pub fn is_revisiting(&self) -> bool {
+        self.revisiting
+    }
+
+    pub fn labeled(&self) -> usize {
+        self.labeled
+    }
+
+    pub fn trashed(&self) -> usize {
+        self.trashed
+    }
+
+    pub fn count(&self, wanted: ImageStatus) -> usize {
+        self.status
+            .iter()
+            .filter(|status| **status == wanted)
+            .count()
+    }
+
+    /// Images that still need to be labeled
+    pub fn open(&self) -> usize {
+        self.status.iter().filter(|status| status.is_open()).count()
+    }
+
+    /// Moves the cursor to the image at `index`
+    pub fn select(&mut self, index: usize) -> &Path {
+        self.cursor = index;
+        self.revisiting = self.status[index] == ImageStatus::Deferred;
+
+        &self.images[index]
+    }
+
+    /// Leaves the image under the cursor for later
+    pub fn defer_current(&mut self) {
+        if let Some(status) = self.status.get_mut(self.cursor) {
+            *status = ImageStatus::Deferred;
+        }
+    }
+
+    /// Index of `image`, if it is part of the queue
+    pub fn position(&self, image: &Path) -> Option<usize> {
+        self.images
+            .binary_search_by(|path| path.as_path().cmp(image))
+            .ok()
+    }
+
+    /// Records that `entry` was applied to its image
+    pub fn record_applied(&mut self, entry: &JournalEntry) {
+        if let Some(index) = self.position(&entry.source) {
+            // Step back onto the image so the queue continues after it, even after a redo
+            self.cursor = index;
+            self.revisiting = self.status[index] == ImageStatus::Deferred;
+        }
+
+        self.mark_applied(entry);
+    }
+
+    /// Records that `entry` was applied to its image, leaving the cursor where it is, e.g. for
+    /// a move that finished after the session was left
+    pub fn mark_applied(&mut self, entry: &JournalEntry) {
+        if let Some(index) = self.position(&entry.source) {
+            self.status[index] = if entry.removes_source() {
+                ImageStatus::Moved
+            } else {
+                ImageStatus::LeftInPlace
+            };
+        }
+
+        if entry.trashed {
+            self.trashed += 1;
+        } else {
+            self.labeled += 1;
+        }
+    }
+
+    /// Records that `entry` was reverted, moving the cursor back onto its image
+    pub fn record_reverted(&mut self, entry: &JournalEntry) {
+        if let Some(index) = self.position(&entry.source) {
+            self.cursor = index;
+            self.revisiting = entry.deferred;
+        }
+
+        self.mark_reverted(entry);
+    }
+
+    /// Records that `entry` was reverted, leaving the cursor where it is
+    pub fn mark_reverted(&mut self, entry: &JournalEntry) {
+        if let Some(index) = self.position(&entry.source) {
+            self.status[index] = if entry.deferred {
+                ImageStatus::Deferred
+            } else {
+                ImageStatus::Pending
+            };
+        }
+
+        if entry.trashed {
+            self.trashed = self.trashed.saturating_sub(1);
+        } else {
+            self.labeled = self.labeled.saturating_sub(1);
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let with_status = |wanted: ImageStatus| {
+            self.images
+                .iter()
+                .zip(&self.status)
+                .filter(move |(_, status)| **status == wanted)
+                .map(|(path, _)| path.clone())
+                .collect()
+        };
+
+        Progress {
+            cursor: self.images.get(self.cursor).cloned(),
+            deferred: with_status(ImageStatus::Deferred),
+            left_in_place: with_status(ImageStatus::LeftInPlace),
+            labeled: self.labeled,
+            trashed: self.trashed,
+        }
+    }
+
+    /// First image with `wanted` status after the cursor, wrapping around to the cursor itself
+    pub fn find_after_cursor(&self, wanted: ImageStatus) -> Option<usize> {
+        let len = self.images.len();
+
+        (1..=len)
+            .map(|offset| (self.cursor + offset) % len)
+            .find(|index| self.status[*index] == wanted)
+    }
+
+    /// Next image to label. Pending images come first, deferred ones are revisited at the end
+    pub fn next_queued(&self) -> Option<usize> {
+        if self.revisiting {
+            self.find_after_cursor(ImageStatus::Deferred)
+                .or_else(|| self.find_after_cursor(ImageStatus::Pending))
+        } else {
+            self.find_after_cursor(ImageStatus::Pending)
+                .or_else(|| self.find_after_cursor(ImageStatus::Deferred))
+        }
+    }
+
+    /// Closest open image before the cursor
+    pub fn previous_open(&self) -> Option<usize> {
+        (0..self.cursor)
+            .rev()
+            .find(|index| self.status[*index].is_open())
+    }
+
+    /// Closest open image after the cursor
+    pub fn next_open(&self) -> Option<usize> {
+        (self.cursor + 1..self.images.len()).find(|index| self.status[*index].is_open())
+    }
+
+    /// Open image matching `query`, either a 1-based position or part of a file name
+    pub fn find(&self, query: &str) -> Option<usize> {
+        let query = query.trim();
+
+        if let Ok(position) = query.parse::<usize>() {
+            return (position.max(1) - 1..self.images.len())
+                .find(|index| self.status[*index].is_open());
+        }
+
+        let query = query.to_lowercase();
+        let len = self.images.len();
+        (1..=len)
+            .map(|offset| (self.cursor + offset) % len)
+            .find(|index| {
+                self.status[*index].is_open()
+                    && self.images[*index]
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().to_lowercase().contains(&query))
+            })
+    }
+}
@@ -0,0 +1,261 @@
+use std::{collections::HashSet, fmt::Display, path::PathBuf};
+
+use crate::{Class, Dataset, dataset::list_images, error::Error, journal::INTENT_LOG_NAME, tokens};
+
+/// A class directory found in the output root
+#[derive(Debug, Clone)]
+pub struct ClassDir {
+    pub class: Class,
+    pub path: PathBuf,
+    /// Images inside the directory, sorted by path
+    pub images: Vec<PathBuf>,
+    /// Extensions of the captions expected next to each image
+    pub caption_extensions: Vec<String>,
+}
+
+impl ClassDir {
+    /// Images with every caption next to them
+    pub fn captioned(&self) -> usize {
+        self.images
+            .iter()
+            .filter(|image| {
+                self.caption_extensions
+                    .iter()
+                    .all(|extension| image.with_extension(extension).is_file())
+            })
+            .count()
+    }
+}
+
+/// What is already in the output root
+#[derive(Debug, Clone, Default)]
+pub struct Scan {
+    /// Directories named `{repeats}_{label}`, sorted by path
+    pub classes: Vec<ClassDir>,
+    /// Directories that aren't named `{repeats}_{label}`
+    pub unrecognized: Vec<PathBuf>,
+}
+
+impl Scan {
+    pub fn images(&self) -> usize {
+        self.classes.iter().map(|dir| dir.images.len()).sum()
+    }
+
+    /// Images times repeats, the number of steps of one epoch
+    pub fn steps(&self) -> usize {
+        self.classes
+            .iter()
+            .map(|dir| dir.images.len() * dir.class.repeats)
+            .sum()
+    }
+}
+
+/// Something in the output root that will trip up training
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A caption output that isn't next to its image
+    MissingCaption(PathBuf),
+    EmptyCaption(PathBuf),
+    /// A caption whose image is gone
+    OrphanedCaption(PathBuf),
+    UnrecognizedDirectory(PathBuf),
+    /// The same label is used with different repeats
+    DuplicateLabel(String),
+    /// A caption that doesn't contain the trigger of its class
+    MissingTrigger(PathBuf, String),
+    /// (caption, tokens of its longest line, max_token_length) of a caption the trainer cuts off
+    TruncatedCaption(PathBuf, usize, usize),
+    /// An operation was interrupted and will be finished when labeling continues
+    Interrupted(PathBuf),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::MissingCaption(caption) => write!(f, "Missing caption {caption:#?}"),
+            Problem::EmptyCaption(caption) => write!(f, "Empty caption {caption:#?}"),
+            Problem::OrphanedCaption(caption) => {
+                write!(f, "Caption {caption:#?} doesn't belong to an image")
+            }
+            Problem::UnrecognizedDirectory(path) => write!(
+                f,
+                "Directory {path:#?} isn't named {{repeats}}_{{label}}, so it won't be trained on"
+            ),
+            Problem::DuplicateLabel(label) => {
+                write!(
+                    f,
+                    "Class {label:?} is in several directories with different repeats"
+                )
+            }
+            Problem::MissingTrigger(caption, trigger) => {
+                write!(
+                    f,
+                    "Caption {caption:#?} doesn't contain the trigger {trigger:?}"
+                )
+            }
+            Problem::TruncatedCaption(caption, tokens, max) => write!(
+                f,
+                "Caption {caption:#?} has {tokens} CLIP tokens, everything past {max} is ignored"
+            ),
+            Problem::Interrupted(log) => write!(
+                f,
+                "An operation was interrupted, it will be finished once labeling continues ({log:#?})"
+            ),
+        }
+    }
+}
+
+impl Dataset {
+    /// Finds the class directories in the output root, ignoring hidden directories and the trash.
+    ///
+    /// Directories of known classes get the class as configured, trigger included.
+    pub fn scan(&self) -> Result<Scan, Error> {
+        let mut scan = self.class_dirs()?;
+        for dir in &mut scan.classes {
+            dir.images = list_images(&dir.path)?;
+        }
+
+        Ok(scan)
+    }
+
+    /// Classes of the directories in the output root, like [`Dataset::scan`] finds them but
+    /// without listing their images, so it is quick even for large datasets
+    pub fn existing_classes(&self) -> Result<Vec<Class>, Error> {
+        Ok(self
+            .class_dirs()?
+            .classes
+            .into_iter()
+            .map(|dir| dir.class)
+            .collect())
+    }
+
+    /// The output root as [`Dataset::scan`] finds it, but without the images
+    fn class_dirs(&self) -> Result<Scan, Error> {
+        let read_dir = std::fs::read_dir(&self.output_dir).map_err(Error::io(format!(
+            "Failed to read output directory {:#?}",
+            self.output_dir
+        )))?;
+
+        let mut scan = Scan::default();
+        for entry in read_dir {
+            let path = entry.map_err(Error::io("Failed to read entry"))?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            if !path.is_dir() || name.starts_with('.') || self.trash_dir.as_ref() == Some(&path) {
+                continue;
+            }
+
+            match Class::from_dir_name(&name) {
+                Some(class) => scan.classes.push(ClassDir {
+                    class: self
+                        .classes
+                        .iter()
+                        .find(|known| known.same_dir(&class))
+                        .cloned()
+                        .unwrap_or(class),
+                    images: Vec::new(),
+                    caption_extensions: self.caption_extensions(),
+                    path,
+                }),
+                None => scan.unrecognized.push(path),
+            }
+        }
+        scan.classes.sort_by(|a, b| a.path.cmp(&b.path));
+        scan.unrecognized.sort();
+
+        Ok(scan)
+    }
+
+    /// Checks the output root for missing captions and other leftovers
+    pub fn validate(&self) -> Result<Vec<Problem>, Error> {
+        let scan = self.scan()?;
+        let mut problems = Vec::new();
+
+        let log = self.output_dir.join(INTENT_LOG_NAME);
+        if log.exists() {
+            problems.push(Problem::Interrupted(log));
+        }
+
+        for dir in &scan.classes {
+            for image in &dir.images {
+                for extension in &dir.caption_extensions {
+                    let caption = image.with_extension(extension);
+                    match std::fs::read_to_string(&caption) {
+                        Ok(contents) if contents.trim().is_empty() => {
+                            problems.push(Problem::EmptyCaption(caption))
+                        }
+                        Ok(contents) => {
+                            if !dir.class.mentions_trigger(&contents) {
+                                problems.push(Problem::MissingTrigger(
+                                    caption.clone(),
+                                    dir.class.trigger.clone().unwrap_or_default(),
+                                ));
+                            }
+
+                            let tokens = tokens::longest_line(&contents);
+                            if tokens > self.max_token_length {
+                                problems.push(Problem::TruncatedCaption(
+                                    caption,
+                                    tokens,
+                                    self.max_token_length,
+                                ));
+                            }
+                        }
+                        Err(_) => problems.push(Problem::MissingCaption(caption)),
+                    }
+                }
+            }
+
+            for caption in orphaned_captions(dir)? {
+                problems.push(Problem::OrphanedCaption(caption));
+            }
+
+            let duplicate = scan.classes.iter().any(|other| {
+                other.class.label == dir.class.label && other.class.repeats != dir.class.repeats
+            });
+            let reported = problems.contains(&Problem::DuplicateLabel(dir.class.label.clone()));
+            if duplicate && !reported {
+                problems.push(Problem::DuplicateLabel(dir.class.label.clone()));
+            }
+        }
+
+        problems.extend(
+            scan.unrecognized
+                .into_iter()
+                .map(Problem::UnrecognizedDirectory),
+        );
+
+        Ok(problems)
+    }
+}
+
+fn orphaned_captions(dir: &ClassDir) -> Result<Vec<PathBuf>, Error> {
+    let read_dir = std::fs::read_dir(&dir.path).map_err(Error::io(format!(
+        "Failed to read directory {:#?}",
+        dir.path
+    )))?;
+
+    // Images without their extension, which is all captions share with them
+    let stems: HashSet<PathBuf> = dir
+        .images
+        .iter()
+        .map(|image| image.with_extension(""))
+        .collect();
+
+    let mut orphaned = Vec::new();
+    for entry in read_dir {
+        let path = entry.map_err(Error::io("Failed to read entry"))?.path();
+
+        let is_caption = path.extension().is_some_and(|extension| {
+            dir.caption_extensions
+                .iter()
+                .any(|caption| extension == caption.as_str())
+        });
+        if is_caption && path.is_file() && !stems.contains(&path.with_extension("")) {
+            orphaned.push(path);
+        }
+    }
+    orphaned.sort();
+
+    Ok(orphaned)
+}
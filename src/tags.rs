@@ -0,0 +1,287 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Dataset, error::Error};
+
+/// Tags that are drawn with underscores, which can't be replaced by spaces
+const KAOMOJI: [&str; 19] = [
+    "0_0", "(o)_(o)", "+_+", "+_-", "._.", "<o>_<o>", "<|>_<|>", "=_=", ">_<", "3_3", "6_9", ">_o",
+    "@_@", "^_^", "o_o", "u_u", "x_x", "|_|", "||_||",
+];
+
+/// How the words of a tag are separated in captions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagStyle {
+    /// `long hair`, like most taggers write for kohya
+    #[default]
+    Spaces,
+    /// `long_hair`, like Danbooru itself
+    Underscores,
+}
+
+impl TagStyle {
+    pub const ALL: [TagStyle; 2] = [TagStyle::Spaces, TagStyle::Underscores];
+}
+
+impl Display for TagStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TagStyle::Spaces => "Spaces (long hair)",
+            TagStyle::Underscores => "Underscores (long_hair)",
+        })
+    }
+}
+
+/// `tag` trimmed, with its words separated the way `style` wants
+pub fn normalize(tag: &str, style: TagStyle) -> String {
+    let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+    if KAOMOJI.contains(&tag.as_str()) {
+        return tag;
+    }
+
+    match style {
+        TagStyle::Spaces => tag.replace('_', " "),
+        TagStyle::Underscores => tag.replace(' ', "_"),
+    }
+}
+
+/// Tags of a comma-separated caption, without empty ones
+pub fn split(caption: &str) -> Vec<String> {
+    caption
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn join(tags: &[String]) -> String {
+    tags.join(", ")
+}
+
+/// `caption` with every tag normalized and duplicates removed
+pub fn normalize_caption(caption: &str, style: TagStyle) -> String {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in split(caption) {
+        let tag = normalize(&tag, style);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    join(&tags)
+}
+
+/// Form of `tag` that matches regardless of its style and case
+fn key(tag: &str) -> String {
+    normalize(tag, TagStyle::Spaces).to_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// Posts with the tag on the booru it comes from
+    pub post_count: u64,
+    /// Other names that mean the same tag
+    pub aliases: Vec<String>,
+    /// Captions in the dataset with the tag
+    pub used: usize,
+}
+
+/// A tag matching what was typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion<'a> {
+    pub tag: &'a Tag,
+    /// Alias that matched, if the name itself didn't
+    pub alias: Option<&'a str>,
+}
+
+/// Tags to complete from, out of a tag list and the captions of the dataset
+#[derive(Debug, Clone, Default)]
+pub struct TagDictionary {
+    tags: Vec<Tag>,
+    /// Keys of the name and then the aliases of each tag, so they needn't be made while typing
+    keys: Vec<Vec<String>>,
+    /// Index of the tag for each key of its name and aliases
+    by_key: HashMap<String, usize>,
+}
+
+impl TagDictionary {
+    /// Reads a tag list like the `danbooru.csv` of the tag autocomplete extension of the
+    /// AUTOMATIC1111 web UI, with the name, category, post count and quoted aliases of a tag on
+    /// every line
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let csv = std::fs::read_to_string(path)
+            .map_err(Error::io(format!("Failed to read tag list {path:#?}")))?;
+
+        Ok(Self::parse_csv(&csv))
+    }
+
+    /// Lines without a tag are skipped, and so are missing post counts and aliases, so lists
+    /// with fewer columns like `name,post_count` are read as well
+    pub fn parse_csv(csv: &str) -> Self {
+        let mut dictionary = Self::default();
+
+        for line in csv.lines() {
+            let fields = csv_fields(line);
+            let name = fields.first().map_or("", |name| name.trim());
+            if name.is_empty() {
+                continue;
+            }
+            // Lists of only names and post counts have the count after the name
+            let count = fields
+                .get(2)
+                .or_else(|| fields.get(1))
+                .map_or("", |count| count.trim());
+            let post_count = if count.is_empty() {
+                0
+            } else {
+                match count.parse() {
+                    Ok(post_count) => post_count,
+                    // A header, or not a tag at all
+                    Err(_) => continue,
+                }
+            };
+            let aliases = fields
+                .get(3)
+                .map(|aliases| split(aliases))
+                .unwrap_or_default();
+
+            dictionary.insert(Tag {
+                name: name.to_string(),
+                post_count,
+                aliases,
+                used: 0,
+            });
+        }
+
+        dictionary
+    }
+
+    /// Counts the uses of `tags` in the dataset, adding the ones that aren't known yet
+    pub fn add_used(&mut self, tags: impl IntoIterator<Item = impl AsRef<str>>) {
+        for tag in tags {
+            let tag = tag.as_ref();
+            match self.by_key.get(&key(tag)) {
+                Some(&index) => self.tags[index].used += 1,
+                None => self.insert(Tag {
+                    name: tag.to_string(),
+                    post_count: 0,
+                    aliases: Vec::new(),
+                    used: 1,
+                }),
+            }
+        }
+    }
+
+    fn insert(&mut self, tag: Tag) {
+        let index = self.tags.len();
+        let keys: Vec<String> = std::iter::once(&tag.name)
+            .chain(&tag.aliases)
+            .map(|name| key(name))
+            .collect();
+        for key in &keys {
+            // The first tag with a name wins, e.g. over an alias of another tag
+            self.by_key.entry(key.clone()).or_insert(index);
+        }
+
+        self.tags.push(tag);
+        self.keys.push(keys);
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Name of the tag `tag` is the name or an alias of, if it is known
+    pub fn canonical(&self, tag: &str) -> Option<&str> {
+        self.by_key
+            .get(&key(tag))
+            .map(|&index| self.tags[index].name.as_str())
+    }
+
+    /// Up to `limit` tags whose name or an alias starts with `typed`, those used in the dataset
+    /// first and then the most popular
+    pub fn suggest(&self, typed: &str, limit: usize) -> Vec<Suggestion<'_>> {
+        let typed = key(typed);
+        if typed.is_empty() {
+            return Vec::new();
+        }
+
+        let mut suggestions: Vec<_> = self
+            .tags
+            .iter()
+            .zip(&self.keys)
+            .filter_map(|(tag, keys)| {
+                let matched = keys.iter().position(|key| key.starts_with(&typed))?;
+
+                Some(Suggestion {
+                    tag,
+                    // The name comes first
+                    alias: matched
+                        .checked_sub(1)
+                        .map(|alias| tag.aliases[alias].as_str()),
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            (b.tag.used, b.tag.post_count)
+                .cmp(&(a.tag.used, a.tag.post_count))
+                // Exact matches before longer ones that are just as popular
+                .then(a.tag.name.len().cmp(&b.tag.name.len()))
+        });
+        suggestions.truncate(limit);
+
+        suggestions
+    }
+}
+
+/// Fields of a CSV line, where quoted fields may contain commas and `""` for quotes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("Starts with a field");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields
+}
+
+impl Dataset {
+    /// Tags of the first caption of every image in the output root
+    pub fn used_tags(&self) -> Result<Vec<String>, Error> {
+        let scan = self.scan()?;
+        let mut tags = Vec::new();
+
+        for dir in &scan.classes {
+            let Some(extension) = dir.caption_extensions.first() else {
+                continue;
+            };
+
+            for image in &dir.images {
+                if let Ok(caption) = std::fs::read_to_string(image.with_extension(extension)) {
+                    tags.extend(split(&caption));
+                }
+            }
+        }
+
+        Ok(tags)
+    }
+}
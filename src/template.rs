@@ -0,0 +1,236 @@
+use std::{fmt::Display, fs::File, io::BufReader, path::Path};
+
+use regex::Regex;
+
+use crate::{
+    dataset::{CLASS_PLACEHOLDER, TRIGGER_PLACEHOLDER},
+    error::Error,
+};
+
+/// Placeholders filled in from the image itself
+pub const PLACEHOLDERS: [&str; 5] = ["filename", "stem", "parent_dir", "width", "height"];
+
+/// Prefix of placeholders filled in from an EXIF tag, e.g. `{exif.DateTimeOriginal}`
+const EXIF_PREFIX: &str = "exif.";
+
+/// Why a template can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownPlaceholder(String),
+    /// A `{` without its `}`
+    Unclosed,
+    /// A `}` without its `{`, which must be doubled to be literal
+    Unopened,
+    /// The file name pattern isn't a valid regex
+    InvalidPattern(String),
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(name) => write!(
+                f,
+                "Unknown placeholder {{{name}}}, use one of {{{}}}, {{class}}, {{trigger}}, {{exif.Tag}} or a capture of the file name pattern",
+                PLACEHOLDERS.join("}, {")
+            ),
+            TemplateError::Unclosed => f.write_str("A { isn't closed, write {{ for a literal {"),
+            TemplateError::Unopened => f.write_str("A } isn't opened, write }} for a literal }"),
+            TemplateError::InvalidPattern(reason) => {
+                write!(f, "Invalid file name pattern: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    FileName,
+    Stem,
+    ParentDir,
+    Width,
+    Height,
+    Exif(String),
+    /// Named or numbered group of the file name pattern
+    Capture(String),
+}
+
+/// A prompt rendered for each image, e.g. `{trigger}, {parent_dir}, taken {exif.DateTimeOriginal}`.
+///
+/// `{class}` and `{trigger}` are kept as they are, they are filled in once the class is known.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+    /// Matched against the file name, its groups are available as `{1}` or `{name}`
+    pattern: Option<Regex>,
+}
+
+impl Template {
+    pub fn parse(source: &str, pattern: Option<&str>) -> Result<Self, TemplateError> {
+        let pattern = pattern
+            .filter(|pattern| !pattern.is_empty())
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| TemplateError::InvalidPattern(e.to_string()))?;
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => text.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => text.push('}'),
+                '}' => return Err(TemplateError::Unopened),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unclosed),
+                        }
+                    }
+
+                    let placeholder = format!("{{{name}}}");
+                    if [CLASS_PLACEHOLDER, TRIGGER_PLACEHOLDER].contains(&placeholder.as_str()) {
+                        // Filled in once the class is known
+                        text.push_str(&placeholder);
+                        continue;
+                    }
+                    let part = Self::placeholder(&name, pattern.as_ref())
+                        .ok_or(TemplateError::UnknownPlaceholder(name))?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Self { parts, pattern })
+    }
+
+    fn placeholder(name: &str, pattern: Option<&Regex>) -> Option<Part> {
+        Some(match name {
+            "filename" => Part::FileName,
+            "stem" => Part::Stem,
+            "parent_dir" => Part::ParentDir,
+            "width" => Part::Width,
+            "height" => Part::Height,
+            _ => {
+                if let Some(tag) = name.strip_prefix(EXIF_PREFIX).filter(|tag| !tag.is_empty()) {
+                    return Some(Part::Exif(tag.to_string()));
+                }
+
+                let pattern = pattern?;
+                let is_capture = match name.parse::<usize>() {
+                    Ok(index) => index < pattern.captures_len(),
+                    Err(_) => pattern.capture_names().any(|group| group == Some(name)),
+                };
+                if !is_capture {
+                    return None;
+                }
+
+                Part::Capture(name.to_string())
+            }
+        })
+    }
+
+    /// The prompt for `image`.
+    ///
+    /// Missing EXIF tags and groups the file name pattern doesn't match are left empty. Only
+    /// failing to read the size of the image is an error.
+    pub fn render(&self, image: &Path) -> Result<String, Error> {
+        let file_name = image.file_name().unwrap_or_default().to_string_lossy();
+        let captures = self
+            .pattern
+            .as_ref()
+            .and_then(|pattern| pattern.captures(&file_name));
+        let mut size = None;
+        let mut exif = None;
+
+        let mut prompt = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => prompt.push_str(text),
+                Part::FileName => prompt.push_str(&file_name),
+                Part::Stem => {
+                    prompt.push_str(&image.file_stem().unwrap_or_default().to_string_lossy())
+                }
+                Part::ParentDir => prompt.push_str(
+                    &image
+                        .parent()
+                        .and_then(Path::file_name)
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                ),
+                Part::Width | Part::Height => {
+                    let size = match size {
+                        Some(size) => size,
+                        None => *size.insert(image_size(image)?),
+                    };
+                    let value = if let Part::Width = part {
+                        size.0
+                    } else {
+                        size.1
+                    };
+                    prompt.push_str(&value.to_string());
+                }
+                Part::Exif(tag) => {
+                    let exif = exif.get_or_insert_with(|| read_exif(image));
+                    if let Some(value) = exif.as_ref().and_then(|exif| exif_value(exif, tag)) {
+                        prompt.push_str(&value);
+                    }
+                }
+                Part::Capture(group) => {
+                    let matched = captures.as_ref().and_then(|captures| match group.parse() {
+                        Ok(index) => captures.get(index),
+                        Err(_) => captures.name(group),
+                    });
+                    if let Some(matched) = matched {
+                        prompt.push_str(matched.as_str());
+                    }
+                }
+            }
+        }
+
+        Ok(prompt)
+    }
+}
+
+/// (width, height) of `image`, read from its header
+fn image_size(image: &Path) -> Result<(usize, usize), Error> {
+    let size = imagesize::size(image).map_err(Error::format(format!(
+        "Failed to read the size of {image:#?}"
+    )))?;
+
+    Ok((size.width, size.height))
+}
+
+/// EXIF data of `image`, if it has any
+fn read_exif(image: &Path) -> Option<exif::Exif> {
+    let file = File::open(image).ok()?;
+
+    exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+fn exif_value(exif: &exif::Exif, tag: &str) -> Option<String> {
+    exif.fields()
+        .find(|field| field.ifd_num == exif::In::PRIMARY && field.tag.to_string() == tag)
+        .map(|field| {
+            field
+                .display_value()
+                .to_string()
+                .trim_matches('"')
+                .to_string()
+        })
+}
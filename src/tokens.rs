@@ -0,0 +1,76 @@
+use std::sync::LazyLock;
+
+use instant_clip_tokenizer::{Token, Tokenizer};
+use regex::Regex;
+
+/// Token lengths kohya's trainers accept for `--max_token_length`, 75 being the default
+pub const MAX_TOKEN_LENGTHS: [usize; 3] = [75, 150, 225];
+
+/// Loading the vocabulary takes a moment, so it is only done once
+static TOKENIZER: LazyLock<Tokenizer> = LazyLock::new(Tokenizer::new);
+
+/// Splits text into the words CLIP encodes separately, like the tokenizer itself does after
+/// lowercasing
+static WORDS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|\p{L}+|\p{N}|[^\s\p{L}\p{N}]+")
+        .expect("Invalid word regex")
+});
+
+/// CLIP tokens of `line`, without the start and end of text tokens.
+///
+/// Trainers read every line of a caption file as a caption of its own, so lines are counted
+/// separately.
+pub fn count(line: &str) -> usize {
+    let mut tokens = Vec::new();
+    WORDS
+        .find_iter(line)
+        .map(|word| {
+            tokens.clear();
+            TOKENIZER.encode(word.as_str(), &mut tokens);
+            tokens.len()
+        })
+        .sum()
+}
+
+/// Tokens of the longest line of `caption`
+pub fn longest_line(caption: &str) -> usize {
+    caption.lines().map(count).max().unwrap_or_default()
+}
+
+/// Byte offset in `line` from which tokens past `max` are cut off, if it has more than that
+pub fn truncation(line: &str, max: usize) -> Option<usize> {
+    let mut kept = 0;
+    let mut tokens = Vec::new();
+
+    for word in WORDS.find_iter(line) {
+        tokens.clear();
+        TOKENIZER.encode(word.as_str(), &mut tokens);
+
+        if kept + tokens.len() <= max {
+            kept += tokens.len();
+            continue;
+        }
+
+        // Part of the word may still fit, which can only be located if lowercasing it didn't
+        // change its length
+        let fitting = decode(&tokens[..max - kept]).len();
+        let offset = if word.as_str().to_lowercase().len() == word.len()
+            && word.as_str().is_char_boundary(fitting.min(word.len()))
+        {
+            fitting.min(word.len())
+        } else {
+            0
+        };
+
+        return Some(word.start() + offset);
+    }
+
+    None
+}
+
+fn decode(tokens: &[Token]) -> String {
+    TOKENIZER
+        .decode(tokens.iter().copied())
+        .trim_end()
+        .to_string()
+}
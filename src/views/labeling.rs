@@ -1,38 +1,554 @@
-use std::path::PathBuf;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use iced::{
     Alignment::Center,
-    Element, Length, Task,
-    widget::{button, column, container, horizontal_space, image, row, text, text_input},
+    Element, Event, Font, Length, Subscription, Task, Theme,
+    advanced::text::{Highlighter, highlighter},
+    event,
+    font::Weight,
+    keyboard,
+    widget::{
+        button, column, container, horizontal_space, image, row, scrollable, text, text::Wrapping,
+        text_editor, text_editor::Binding, text_input,
+    },
 };
 
-use crate::{Message, SharedState};
+use quicklabel::{
+    Error, Generation, ImageStatus, LabelQueue, Progress,
+    collision::CollisionPolicy,
+    journal::{JournalEntry, PlannedMove},
+    metadata::strip_prompt_syntax,
+    tags::{self, TagDictionary},
+    template::Template,
+    tokens, xmp,
+};
 
-pub const IMAGE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+use crate::{Message, SharedState, project::Project};
 
 #[derive(Debug, Clone, Default)]
 pub struct LabelingState {
-    images: Vec<PathBuf>,
-    images_cursor: usize,
+    queue: LabelQueue,
     current_image: Option<PathBuf>,
-    input_prompt: String,
+    /// Editor of each caption field
+    input_prompts: Vec<CaptionEditor>,
+    goto_input: String,
+    /// Progress to continue from once indexing finishes
+    resume_from: Option<Progress>,
+    undo_stack: Vec<JournalEntry>,
+    redo_stack: Vec<JournalEntry>,
+    /// A file operation or loading the current image is in progress, further actions are ignored
+    /// until it completes
+    busy: bool,
+    /// Number of images loaded, so only the details of the last one are shown
+    loads: u64,
+    /// Number of changes to the progress, so it is only saved after the last of a quick series
+    progress_changes: u64,
+    /// The progress changed since it was last saved
+    unsaved: bool,
+    /// Move waiting for the user to decide how to handle a file name collision
+    collision: Option<PlannedMove>,
+    /// Failed step waiting for the user to decide what to do
+    failure: Option<Failure>,
+    /// Warning about the last submitted caption or the current image
+    warning: Option<String>,
+    /// How the current image was generated, if it says so
+    generation: Option<Generation>,
+    /// Tags to complete from in tag mode
+    tags: Arc<TagDictionary>,
+    /// Template of the pre-filled prompt, parsed once labeling began
+    template: Option<Template>,
+}
+
+impl LabelingState {
+    pub fn resume(progress: Progress, template: Option<Template>) -> Self {
+        Self {
+            resume_from: Some(progress),
+            template,
+            ..Default::default()
+        }
+    }
+}
+
+/// Multi-line editor of a caption field
+#[derive(Debug, Default)]
+struct CaptionEditor {
+    content: text_editor::Content,
+    /// Tag being typed in tag mode, added to the caption once it is complete
+    tag: String,
+    /// What the tag being typed may complete to, found as it is typed
+    suggestions: Vec<TagSuggestion>,
+}
+
+/// Tag the one being typed may complete to
+#[derive(Debug, Clone)]
+struct TagSuggestion {
+    /// Name added to the caption when it is picked
+    tag: String,
+    label: String,
+}
+
+// The contents of the editor can't be cloned, so only its text is
+impl Clone for CaptionEditor {
+    fn clone(&self) -> Self {
+        Self {
+            tag: self.tag.clone(),
+            suggestions: self.suggestions.clone(),
+            ..Self::new(&self.text())
+        }
+    }
+}
+
+impl CaptionEditor {
+    /// Editor holding `text`, with the cursor at its end
+    fn new(text: &str) -> Self {
+        let mut content = text_editor::Content::with_text(text);
+        content.perform(text_editor::Action::Move(text_editor::Motion::DocumentEnd));
+
+        Self {
+            content,
+            tag: String::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut text = self.content.text();
+        // The editor always ends the text with a newline
+        if text.ends_with('\n') {
+            text.pop();
+        }
+
+        text
+    }
+
+    /// Editor holding `caption`, with its tags normalized in tag mode
+    fn prefilled(shared: &SharedState, caption: &str) -> Self {
+        if shared.tag_mode {
+            Self::new(&tags::normalize_caption(caption, shared.tag_style))
+        } else {
+            Self::new(caption)
+        }
+    }
+
+    /// Adds the tags in `typed` that aren't in the caption yet, with aliases replaced by the tag
+    /// they stand for
+    fn add_tags(&mut self, shared: &SharedState, dictionary: &TagDictionary, typed: &str) {
+        let mut caption = tags::split(&self.text());
+        for tag in tags::split(typed) {
+            let tag = tags::normalize(dictionary.canonical(&tag).unwrap_or(&tag), shared.tag_style);
+            if !caption.contains(&tag) {
+                caption.push(tag);
+            }
+        }
+
+        *self = Self {
+            tag: std::mem::take(&mut self.tag),
+            ..Self::new(&tags::join(&caption))
+        };
+    }
+
+    /// Finds what the typed tag may complete to, other than tags in the caption already.
+    /// Searching the dictionary takes a while, so it is done as the tag changes rather than
+    /// whenever the view is drawn.
+    fn suggest(&mut self, shared: &SharedState, dictionary: &TagDictionary) {
+        let caption = tags::split(&self.text());
+
+        self.suggestions = dictionary
+            .suggest(&self.tag, SUGGESTIONS)
+            .into_iter()
+            .filter(|suggestion| {
+                !caption.contains(&tags::normalize(&suggestion.tag.name, shared.tag_style))
+            })
+            .map(|suggestion| {
+                let tag = tags::normalize(&suggestion.tag.name, shared.tag_style);
+                let mut label = match suggestion.alias {
+                    Some(alias) => format!("{} → {tag}", tags::normalize(alias, shared.tag_style)),
+                    None => tag.clone(),
+                };
+                if suggestion.tag.post_count != 0 {
+                    label.push_str(&format!(
+                        " ({})",
+                        thousands(suggestion.tag.post_count as usize)
+                    ));
+                }
+
+                TagSuggestion { tag, label }
+            })
+            .collect();
+    }
+}
+
+/// Highlights the part of each caption line the trainer cuts off, the settings being its
+/// `max_token_length`
+struct TruncationHighlighter {
+    max_token_length: usize,
+    current_line: usize,
+}
+
+impl Highlighter for TruncationHighlighter {
+    type Settings = usize;
+    type Highlight = ();
+    type Iterator<'a> = std::option::IntoIter<(Range<usize>, ())>;
+
+    fn new(max_token_length: &usize) -> Self {
+        Self {
+            max_token_length: *max_token_length,
+            current_line: 0,
+        }
+    }
+
+    fn update(&mut self, max_token_length: &usize) {
+        self.max_token_length = *max_token_length;
+        self.current_line = 0;
+    }
+
+    fn change_line(&mut self, line: usize) {
+        self.current_line = line;
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        self.current_line += 1;
+        tokens::truncation(line, self.max_token_length)
+            .map(|start| (start..line.len(), ()))
+            .into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
+
+/// A file operation that can be retried if it fails
+#[derive(Debug, Clone)]
+pub enum Operation {
+    /// Label or trash the current image
+    Move(PlannedMove, CollisionPolicy),
+    /// Finish a move whose destination was decided already, but which failed partway
+    Finish(JournalEntry),
+    /// Redo an undone entry
+    Apply(JournalEntry),
+    /// Undo an entry
+    Revert(JournalEntry),
+}
+
+/// A failed step, shown to the user until they decide how to continue
+#[derive(Debug, Clone)]
+pub struct Failure {
+    error: Error,
+    /// Message that retries the failed step
+    retry: Box<LabelingMessage>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FailureChoice {
+    Retry,
+    /// Give up on the step and defer the current image
+    SkipImage,
+    /// Give up on the step
+    Abort,
 }
 
 #[derive(Debug, Clone)]
 pub enum LabelingMessage {
-    SetPrompt(String),
+    /// (field index, edit)
+    EditPrompt(usize, text_editor::Action),
+    /// Replace the first prompt with the one the current image was generated with
+    UseGenerationPrompt,
+    TagsLoaded(Result<Arc<TagDictionary>, Error>),
+    /// (field index, tag as typed so far). Tags followed by a comma are added right away
+    SetTagInput(usize, String),
+    /// Add the typed tag to the field at the index, or submit the default class if there is none
+    AddTag(usize),
+    /// (field index, tag) picked from the suggestions
+    AddSuggestion(usize, String),
+    /// (field index, tag index)
+    RemoveTag(usize, usize),
     /// class index
     SubmitLabel(usize),
     Index,
     FinishIndexing(Vec<PathBuf>),
+    /// Go to the next image in the queue
     NextImage,
+    /// Browse to the previous open image, without labeling the current one
+    Previous,
+    /// Browse to the next open image, without labeling the current one
+    Next,
+    SetGoTo(String),
+    /// Go to the image at the position or with the file name in the go to input
+    GoTo,
     NoImagesLeft,
     TrashCurrent,
+    /// Leave the current image in the input directory and revisit it later
+    Skip,
+    /// Revisit the deferred images now, rather than at the end
+    RevisitDeferred,
+    /// (key, modifiers, captured by a widget)
+    KeyPressed(keyboard::Key, keyboard::Modifiers, bool),
+    /// The destination of the move is taken and the user has to decide what to do
+    Collided(PlannedMove),
+    /// None to cancel the move
+    ResolveCollision(Option<CollisionPolicy>),
+    Perform(Operation),
+    Failed(Failure),
+    ResolveFailure(FailureChoice),
+    /// Give up on the failed step, after rolling back what it left behind
+    GiveUp(Box<LabelingMessage>, FailureChoice),
+    SaveProgress,
+    /// The progress changed as often as the number says and browsing has settled since
+    SaveSettled(u64),
+    /// (load number, details) of the current image
+    Loaded(u64, Box<ImageDetails>),
+    /// The move was performed and can now be undone
+    Applied(JournalEntry),
+    Undo,
+    /// The move was undone and can now be redone
+    Reverted(JournalEntry),
+    Redo,
+}
+
+impl From<LabelingMessage> for Message {
+    fn from(message: LabelingMessage) -> Self {
+        Message::Labeling(message)
+    }
+}
+
+/// Moves the cursor to the image at `index` and shows it
+fn show(shared: &SharedState, local: &mut LabelingState, index: usize) -> Task<Message> {
+    let image = local.queue.select(index).to_path_buf();
+    local.current_image = Some(image.clone());
+    local.generation = None;
+
+    Task::batch([load(shared, local, image, true), save_later(local)])
+}
+
+/// What the files of an image say about it, read in the background so browsing doesn't wait for
+/// the disk
+#[derive(Debug, Clone)]
+pub struct ImageDetails {
+    generation: Option<Generation>,
+    /// Caption to pre-fill each field with, empty if the fields are left alone
+    prompts: Vec<Option<String>>,
+    /// Why some of it couldn't be read
+    warning: Option<String>,
+}
+
+/// Reads the details of `image` in the background, with the captions to pre-fill if `prefill`
+fn load(
+    shared: &SharedState,
+    local: &mut LabelingState,
+    image: PathBuf,
+    prefill: bool,
+) -> Task<Message> {
+    local.busy = true;
+    local.loads += 1;
+
+    let load = local.loads;
+    let shared = shared.clone();
+    let template = local.template.clone();
+    Task::perform(
+        async move { read_details(&shared, template.as_ref(), &image, prefill) },
+        move |details| LabelingMessage::Loaded(load, Box::new(details)).into(),
+    )
+}
+
+fn read_details(
+    shared: &SharedState,
+    template: Option<&Template>,
+    image: &Path,
+    prefill: bool,
+) -> ImageDetails {
+    let mut warning = None;
+
+    let mut prompt = None;
+    if let Some(template) = template.filter(|_| prefill) {
+        match template.render(image) {
+            Ok(prefill) => prompt = Some(prefill),
+            Err(e) => warning = Some(e.to_string()),
+        }
+    }
+
+    // Unreadable files are worth a warning, not failing over
+    let generation = Generation::read(image).unwrap_or_else(|e| {
+        warning = Some(e.to_string());
+        None
+    });
+
+    if !prefill {
+        return ImageDetails {
+            generation,
+            prompts: Vec::new(),
+            warning,
+        };
+    }
+
+    // With several fields, each one is pre-filled from the sidecars named like its outputs
+    let fields = shared.dataset.caption_fields();
+    let sidecar_caption = |field: &str| {
+        if fields.len() > 1 {
+            shared.dataset.field_sidecar_caption(image, field)
+        } else {
+            shared.dataset.sidecar_caption(image)
+        }
+    };
+
+    let caption = fields
+        .first()
+        .and_then(|field| sidecar_caption(field))
+        .or_else(|| read_xmp(shared, image, &mut warning))
+        .or_else(|| {
+            generation
+                .as_ref()
+                .filter(|_| shared.generation_prefill)
+                .map(|generation| generation_prompt(shared, generation))
+        });
+    if let Some(caption) = caption {
+        prompt = Some(match prompt {
+            Some(prefill) if shared.merge_sidecars && !prefill.trim().is_empty() => {
+                format!("{}, {caption}", prefill.trim_end_matches([',', ' ']))
+            }
+            _ => caption,
+        });
+    }
+
+    let prompts = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            if index == 0 {
+                prompt.take()
+            } else {
+                sidecar_caption(field)
+            }
+        })
+        .collect();
+
+    ImageDetails {
+        generation,
+        prompts,
+        warning,
+    }
+}
+
+/// Puts `prompts` back into the caption fields, e.g. once their image is back
+fn give_back(shared: &SharedState, local: &mut LabelingState, prompts: Vec<String>) {
+    local.input_prompts = prompts
+        .iter()
+        .map(|prompt| CaptionEditor::new(prompt))
+        .collect();
+    local.input_prompts.resize_with(
+        shared.dataset.caption_fields().len(),
+        CaptionEditor::default,
+    );
+}
+
+/// Caption of the XMP metadata of `image` if the user wants it, warning about unreadable files
+fn read_xmp(shared: &SharedState, image: &Path, warning: &mut Option<String>) -> Option<String> {
+    if !shared.xmp_prefill {
+        return None;
+    }
+
+    match xmp::read(image) {
+        Ok(caption) => caption.and_then(|caption| caption.caption()),
+        Err(e) => {
+            *warning = Some(e.to_string());
+            None
+        }
+    }
+}
+
+/// Positive prompt of `generation`, stripped of LoRA tags and weights if the user wants that
+fn generation_prompt(shared: &SharedState, generation: &Generation) -> String {
+    if shared.strip_prompt_syntax {
+        strip_prompt_syntax(&generation.prompt)
+    } else {
+        generation.prompt.clone()
+    }
+}
+
+/// Records an `Applied` or `Reverted` result of a file operation started before the labeling
+/// view was left and entered again, leaving the cursor where it is
+pub fn record_stale(
+    shared: &SharedState,
+    local: &mut LabelingState,
+    message: LabelingMessage,
+) -> Task<Message> {
+    // Still indexing, so the queue will be built from the progress to resume from
+    if let Some(progress) = &mut local.resume_from {
+        match &message {
+            LabelingMessage::Applied(entry) => progress.record_applied(entry),
+            LabelingMessage::Reverted(entry) => progress.record_reverted(entry),
+            _ => {}
+        }
+
+        return Task::none();
+    }
+
+    match &message {
+        LabelingMessage::Applied(entry) => local.queue.mark_applied(entry),
+        LabelingMessage::Reverted(entry) => local.queue.mark_reverted(entry),
+        _ => return Task::none(),
+    }
+
+    save_progress(shared, local)
+}
+
+/// How long browsing has to settle before the progress is saved
+const SAVE_DELAY: Duration = Duration::from_millis(500);
+
+fn save_progress(shared: &SharedState, local: &mut LabelingState) -> Task<Message> {
+    match write_progress(shared, local) {
+        Ok(()) => Task::none(),
+        Err(e) => Task::done(fail(e, LabelingMessage::SaveProgress)),
+    }
+}
+
+/// Saves the progress once browsing settles, rather than for every image passed on the way
+fn save_later(local: &mut LabelingState) -> Task<Message> {
+    local.unsaved = true;
+    local.progress_changes += 1;
+
+    let change = local.progress_changes;
+    Task::perform(tokio::time::sleep(SAVE_DELAY), move |()| {
+        LabelingMessage::SaveSettled(change).into()
+    })
+}
+
+/// Saves progress that is still waiting for browsing to settle, e.g. before the view is left
+pub fn save_pending(shared: &SharedState, local: &mut LabelingState) -> Result<(), Error> {
+    if local.unsaved {
+        write_progress(shared, local)?;
+    }
+
+    Ok(())
+}
+
+fn write_progress(shared: &SharedState, local: &mut LabelingState) -> Result<(), Error> {
+    let project = Project {
+        session: shared.clone(),
+        progress: local.queue.progress(),
+    };
+
+    project.save()?;
+    local.unsaved = false;
+
+    Ok(())
 }
 
-impl Into<Message> for LabelingMessage {
-    fn into(self) -> Message {
-        Message::Labeling(self)
+/// Reports `error` to the user, offering to retry with `retry` if it is recoverable
+fn fail(error: Error, retry: LabelingMessage) -> Message {
+    if error.is_recoverable() {
+        LabelingMessage::Failed(Failure {
+            error,
+            retry: Box::new(retry),
+        })
+        .into()
+    } else {
+        Message::FatalError(error.to_string())
     }
 }
 
@@ -43,84 +559,176 @@ pub fn update(
 ) -> Task<Message> {
     match message {
         LabelingMessage::Index => {
-            let input_dir = shared.input_dir.clone();
-            return Task::perform(
+            let dataset = shared.dataset.clone();
+            let index = Task::perform(
                 async move {
-                    let read_dir = match std::fs::read_dir(&input_dir) {
-                        Ok(read_dir) => read_dir,
-                        Err(e) => {
-                            return Message::FatalError(format!(
-                                "Failed to read input directory: {e}"
-                            ));
-                        }
-                    };
-
-                    let mut images = Vec::new();
-                    for entry in read_dir {
-                        match entry {
-                            Ok(entry) => {
-                                let path = entry.path();
-                                if path.is_file()
-                                    && IMAGE_EXTENSIONS.contains(
-                                        &path
-                                            .extension()
-                                            .unwrap_or_default()
-                                            .display()
-                                            .to_string()
-                                            .to_lowercase()
-                                            .as_str(),
-                                    )
-                                {
-                                    images.push(path);
-                                }
-                            }
-                            Err(e) => {
-                                return Message::FatalError(format!("Failed to read entry: {e}"));
-                            }
-                        }
+                    match dataset.index() {
+                        Ok(images) => LabelingMessage::FinishIndexing(images).into(),
+                        Err(e) => fail(e, LabelingMessage::Index),
                     }
-                    return LabelingMessage::FinishIndexing(images).into();
                 },
                 |out| out,
             );
+
+            if !shared.tag_mode {
+                return index;
+            }
+
+            let dataset = shared.dataset.clone();
+            let tag_list = shared.tag_list.clone();
+            let load_tags = Task::perform(
+                async move {
+                    let mut dictionary = match &tag_list {
+                        Some(path) => TagDictionary::load(path)?,
+                        None => TagDictionary::default(),
+                    };
+                    dictionary.add_used(dataset.used_tags()?);
+
+                    Ok(Arc::new(dictionary))
+                },
+                |tags| LabelingMessage::TagsLoaded(tags).into(),
+            );
+
+            return Task::batch([index, load_tags]);
         }
 
+        LabelingMessage::TagsLoaded(tags) => match tags {
+            Ok(tags) => {
+                local.tags = tags;
+                // Tags typed while the dictionary was loading can be completed now
+                for input in &mut local.input_prompts {
+                    input.suggest(shared, &local.tags);
+                }
+            }
+            // Labeling works without completions, so this is only worth a warning
+            Err(e) => local.warning = Some(format!("Tags can't be completed: {e}")),
+        },
+
         LabelingMessage::FinishIndexing(images) => {
             if images.is_empty() {
                 return Task::done(Message::FatalError("No images found".to_string()));
             }
 
-            local.images = images;
-            local.current_image = Some(
-                local
-                    .images
-                    .first()
-                    .expect("No first image despite length check?!")
-                    .clone(),
-            );
-            local.images_cursor = 0;
+            let progress = local.resume_from.take().unwrap_or_default();
+            local.queue = LabelQueue::new(images, progress);
 
-            if let Some(prefill) = &shared.prompt_prefill {
-                local.input_prompt = prefill.clone();
+            let cursor = local.queue.cursor();
+            if local.queue.status(cursor).is_open() {
+                return show(shared, local, cursor);
             }
+
+            return Task::done(LabelingMessage::NextImage.into());
         }
 
         LabelingMessage::NextImage => {
-            local.images_cursor += 1;
-
-            if let Some(path) = local.images.get(local.images_cursor) {
-                local.current_image = Some(path.clone());
-            } else {
+            let Some(index) = local.queue.next_queued() else {
                 return Task::done(LabelingMessage::NoImagesLeft.into());
+            };
+
+            return show(shared, local, index);
+        }
+
+        LabelingMessage::Previous if !local.busy => {
+            if let Some(index) = local.queue.previous_open() {
+                return show(shared, local, index);
+            }
+        }
+
+        LabelingMessage::Next if !local.busy => {
+            if let Some(index) = local.queue.next_open() {
+                return show(shared, local, index);
+            }
+        }
+
+        LabelingMessage::SetGoTo(value) => {
+            local.goto_input = value;
+        }
+
+        LabelingMessage::GoTo if !local.busy => {
+            if let Some(index) = local.queue.find(&local.goto_input) {
+                local.goto_input.clear();
+                return show(shared, local, index);
+            }
+        }
+
+        LabelingMessage::Skip if !local.busy => {
+            if local.current_image.is_some() {
+                local.queue.defer_current();
+            }
+
+            local.redo_stack.clear();
+            return Task::done(LabelingMessage::NextImage.into());
+        }
+
+        LabelingMessage::RevisitDeferred if !local.busy => {
+            if let Some(index) = local.queue.find_after_cursor(ImageStatus::Deferred) {
+                return show(shared, local, index);
             }
+        }
 
-            if let Some(prefill) = &shared.prompt_prefill {
-                local.input_prompt = prefill.clone();
+        LabelingMessage::EditPrompt(index, action) => {
+            if let Some(input) = local.input_prompts.get_mut(index) {
+                input.content.perform(action);
             }
         }
 
-        LabelingMessage::SetPrompt(value) => {
-            local.input_prompt = value;
+        LabelingMessage::UseGenerationPrompt => {
+            if let (Some(generation), Some(input)) =
+                (&local.generation, local.input_prompts.first_mut())
+            {
+                *input = CaptionEditor::prefilled(shared, &generation_prompt(shared, generation));
+            }
+        }
+
+        LabelingMessage::SetTagInput(index, typed) => {
+            if let Some(input) = local.input_prompts.get_mut(index) {
+                match typed.rsplit_once(',') {
+                    Some((complete, rest)) => {
+                        input.add_tags(shared, &local.tags, complete);
+                        input.tag = rest.trim_start().to_string();
+                    }
+                    None => input.tag = typed,
+                }
+                input.suggest(shared, &local.tags);
+            }
+        }
+
+        LabelingMessage::AddTag(index) => {
+            if let Some(input) = local.input_prompts.get_mut(index) {
+                if input.tag.trim().is_empty() {
+                    return shared.default_class.map_or(Task::none(), |class| {
+                        Task::done(LabelingMessage::SubmitLabel(class).into())
+                    });
+                }
+
+                let typed = std::mem::take(&mut input.tag);
+                input.add_tags(shared, &local.tags, &typed);
+                input.suggest(shared, &local.tags);
+            }
+        }
+
+        LabelingMessage::AddSuggestion(index, tag) => {
+            if let Some(input) = local.input_prompts.get_mut(index) {
+                input.tag.clear();
+                input.add_tags(shared, &local.tags, &tag);
+                input.suggest(shared, &local.tags);
+            }
+        }
+
+        LabelingMessage::RemoveTag(index, tag) => {
+            if let Some(input) = local.input_prompts.get_mut(index) {
+                let mut caption = tags::split(&input.text());
+                if tag < caption.len() {
+                    caption.remove(tag);
+                }
+
+                *input = CaptionEditor {
+                    tag: std::mem::take(&mut input.tag),
+                    ..CaptionEditor::new(&tags::join(&caption))
+                };
+                // The removed tag may be suggested again
+                input.suggest(shared, &local.tags);
+            }
         }
 
         LabelingMessage::NoImagesLeft => {
@@ -130,102 +738,718 @@ pub fn update(
             ));
         }
 
-        LabelingMessage::TrashCurrent => {
-            let current_image = local
-                .current_image
-                .clone()
-                .expect("Submitting without image?!");
+        LabelingMessage::TrashCurrent if !local.busy => {
+            let Some(current_image) = local.current_image.clone() else {
+                return Task::none();
+            };
+            local.busy = true;
+
+            local.redo_stack.clear();
+            return perform(
+                Operation::Move(
+                    PlannedMove {
+                        // Kept so undoing gives the prompts back
+                        prompts: local
+                            .input_prompts
+                            .iter()
+                            .map(CaptionEditor::text)
+                            .collect(),
+                        deferred: local.queue.is_revisiting(),
+                        ..shared.dataset.plan_trash(&current_image)
+                    },
+                    shared.dataset.collision_policy,
+                ),
+                shared.dataset.output_dir.clone(),
+            );
+        }
 
-            if let Some(mut trash_destination) = shared.trash_dir.clone() {
-                trash_destination.push(&current_image.file_name().unwrap());
+        LabelingMessage::SubmitLabel(class_index) if !local.busy => {
+            let (Some(current_image), Some(class)) = (
+                local.current_image.clone(),
+                shared.dataset.classes.get(class_index),
+            ) else {
+                return Task::none();
+            };
+            local.busy = true;
 
-                return Task::perform(
-                    async move {
-                        if let Err(e) = std::fs::copy(&current_image, &trash_destination) {
-                            return Message::FatalError(format!(
-                                "Failed to move {current_image:#?} to {trash_destination:#?}: {e}"
-                            ));
-                        }
+            // Tags that are still being typed belong to the caption too
+            for input in &mut local.input_prompts {
+                let typed = std::mem::take(&mut input.tag);
+                input.add_tags(shared, &local.tags, &typed);
+                input.suggest(shared, &local.tags);
+            }
 
-                        if let Err(e) = std::fs::remove_file(&current_image) {
-                            return Message::FatalError(format!(
-                                "Failed to delete {current_image:#?}: {e}"
-                            ));
-                        }
+            let captions: Vec<String> = std::mem::take(&mut local.input_prompts)
+                .iter()
+                .map(|prompt| {
+                    if shared.manual_triggers {
+                        class.fill_placeholders(&prompt.text())
+                    } else {
+                        class.insert_trigger(&prompt.text())
+                    }
+                })
+                .collect();
+            local.warning = class
+                .trigger
+                .as_ref()
+                .filter(|_| {
+                    captions
+                        .iter()
+                        .any(|caption| !class.mentions_trigger(caption))
+                })
+                .map(|trigger| {
+                    format!(
+                        "The caption of {:?} doesn't contain the trigger {trigger:?} of {:?}",
+                        current_image.file_name().unwrap_or_default(),
+                        class.label
+                    )
+                });
 
-                        LabelingMessage::NextImage.into()
+            local.redo_stack.clear();
+            return perform(
+                Operation::Move(
+                    PlannedMove {
+                        deferred: local.queue.is_revisiting(),
+                        ..shared
+                            .dataset
+                            .plan_label_fields(&current_image, class, &captions)
                     },
-                    |out| out,
-                );
-            } else {
-                // If there isn't a trash dir, have trashed images remain in input dir
-                return Task::done(LabelingMessage::NextImage.into());
+                    shared.dataset.collision_policy,
+                ),
+                shared.dataset.output_dir.clone(),
+            );
+        }
+
+        LabelingMessage::Collided(planned) => {
+            local.collision = Some(planned);
+        }
+
+        LabelingMessage::ResolveCollision(policy) => {
+            if let Some(planned) = local.collision.take() {
+                if let Some(policy) = policy {
+                    return perform(
+                        Operation::Move(planned, policy),
+                        shared.dataset.output_dir.clone(),
+                    );
+                }
+
+                // Cancelled, so give the prompts back
+                local.busy = false;
+                give_back(shared, local, planned.prompts);
+            }
+        }
+
+        LabelingMessage::Applied(entry) => {
+            local.busy = false;
+            if shared.tag_mode
+                && let Some(caption) = entry.captions.first()
+            {
+                Arc::make_mut(&mut local.tags).add_used(tags::split(&caption.contents));
+            }
+            local.queue.record_applied(&entry);
+            local.undo_stack.push(entry);
+
+            // Saved right away, the files moved already
+            let save = save_progress(shared, local);
+            return Task::batch([save, Task::done(LabelingMessage::NextImage.into())]);
+        }
+
+        LabelingMessage::Undo if !local.busy => {
+            if let Some(entry) = local.undo_stack.pop() {
+                local.busy = true;
+                return perform(Operation::Revert(entry), shared.dataset.output_dir.clone());
+            }
+        }
+
+        LabelingMessage::Reverted(entry) => {
+            local.busy = false;
+            local.queue.record_reverted(&entry);
+            local.current_image = Some(entry.source.clone());
+            local.generation = None;
+            give_back(shared, local, entry.prompts.clone());
+            let load = load(shared, local, entry.source.clone(), false);
+            local.redo_stack.push(entry);
+
+            // Saved right away, the files moved already
+            return Task::batch([save_progress(shared, local), load]);
+        }
+
+        LabelingMessage::Redo if !local.busy => {
+            if let Some(entry) = local.redo_stack.pop() {
+                local.busy = true;
+                return perform(Operation::Apply(entry), shared.dataset.output_dir.clone());
             }
         }
 
-        LabelingMessage::SubmitLabel(class_index) => {
-            let current_image = local
-                .current_image
-                .clone()
-                .expect("Submitting without image?!");
+        LabelingMessage::Perform(operation) => {
+            local.busy = true;
+            return perform(operation, shared.dataset.output_dir.clone());
+        }
 
-            let mut destination_path = shared.classes[class_index].path(shared.output_dir.clone());
-            destination_path.push(&current_image.file_name().unwrap());
+        LabelingMessage::Failed(failure) => {
+            local.failure = Some(failure);
+        }
 
-            let prompt = std::mem::take(&mut local.input_prompt);
+        LabelingMessage::ResolveFailure(choice) => {
+            let Some(failure) = local.failure.take() else {
+                return Task::none();
+            };
 
+            if let FailureChoice::Retry = choice {
+                return Task::done((*failure.retry).into());
+            }
+
+            // Files the step got to may still be there
+            let (entry, revert) = match &*failure.retry {
+                LabelingMessage::Perform(Operation::Finish(entry) | Operation::Apply(entry)) => {
+                    (entry.clone(), false)
+                }
+                LabelingMessage::Perform(Operation::Revert(entry)) => (entry.clone(), true),
+                _ => return give_up(shared, local, *failure.retry, choice),
+            };
+
+            let output_dir = shared.dataset.output_dir.clone();
             return Task::perform(
                 async move {
-                    if let Err(e) = std::fs::copy(&current_image, &destination_path) {
-                        return Message::FatalError(format!(
-                            "Failed to copy image from {current_image:#?} to {destination_path:#?}: {e}"
-                        ));
+                    match entry.settle(&output_dir, revert) {
+                        // The step was finished after all
+                        Ok(true) if revert => LabelingMessage::Reverted(entry).into(),
+                        Ok(true) => LabelingMessage::Applied(entry).into(),
+                        Ok(false) => LabelingMessage::GiveUp(failure.retry, choice).into(),
+                        Err(e) => fail(e, *failure.retry),
                     }
+                },
+                |out| out,
+            );
+        }
+
+        LabelingMessage::GiveUp(step, choice) => {
+            return give_up(shared, local, *step, choice);
+        }
+
+        LabelingMessage::SaveProgress => {
+            return save_progress(shared, local);
+        }
+
+        LabelingMessage::SaveSettled(change) => {
+            if change == local.progress_changes && local.unsaved {
+                return save_progress(shared, local);
+            }
+        }
+
+        LabelingMessage::Loaded(load, details) => {
+            // Superseded by the image shown after it
+            if load != local.loads {
+                return Task::none();
+            }
+
+            local.busy = false;
+            let ImageDetails {
+                generation,
+                prompts,
+                warning,
+            } = *details;
+            local.generation = generation;
+            if warning.is_some() {
+                local.warning = warning;
+            }
+
+            if !prompts.is_empty() {
+                local
+                    .input_prompts
+                    .resize_with(prompts.len(), CaptionEditor::default);
+            }
+            for (input, prompt) in local.input_prompts.iter_mut().zip(prompts) {
+                if let Some(prompt) = prompt {
+                    *input = CaptionEditor::prefilled(shared, &prompt);
+                }
+            }
+        }
+
+        LabelingMessage::KeyPressed(key, modifiers, captured) => {
+            return handle_key(shared, local, key, modifiers, captured);
+        }
+
+        LabelingMessage::TrashCurrent
+        | LabelingMessage::SubmitLabel(..)
+        | LabelingMessage::Undo
+        | LabelingMessage::Redo
+        | LabelingMessage::Skip
+        | LabelingMessage::RevisitDeferred
+        | LabelingMessage::Previous
+        | LabelingMessage::Next
+        | LabelingMessage::GoTo => {}
+    }
+    Task::none()
+}
+
+/// Puts everything back the way it was before the failed `step`
+fn give_up(
+    shared: &SharedState,
+    local: &mut LabelingState,
+    step: LabelingMessage,
+    choice: FailureChoice,
+) -> Task<Message> {
+    local.busy = false;
+    match step {
+        LabelingMessage::Perform(Operation::Move(planned, ..)) => {
+            give_back(shared, local, planned.prompts);
+        }
+        LabelingMessage::Perform(Operation::Finish(entry)) => {
+            give_back(shared, local, entry.prompts);
+        }
+        LabelingMessage::Perform(Operation::Apply(entry)) => local.redo_stack.push(entry),
+        LabelingMessage::Perform(Operation::Revert(entry)) => local.undo_stack.push(entry),
+        LabelingMessage::Index => return Task::done(Message::GoOptions),
+        _ => {}
+    }
+
+    if let FailureChoice::SkipImage = choice {
+        return Task::done(LabelingMessage::Skip.into());
+    }
 
-                    if let Err(e) = std::fs::remove_file(&current_image) {
-                        return Message::FatalError(format!(
-                            "Failed to remove image {current_image:#?}: {e}"
-                        ));
+    Task::none()
+}
+
+fn perform(operation: Operation, output_dir: PathBuf) -> Task<Message> {
+    Task::perform(
+        async move {
+            let result = match &operation {
+                Operation::Move(planned, policy) => match planned.resolve(*policy) {
+                    Ok(Some(entry)) => {
+                        return match entry.apply(&output_dir) {
+                            Ok(()) => LabelingMessage::Applied(entry).into(),
+                            // Planning again could pick another destination, while the image may
+                            // be at this one already
+                            Err(e) => fail(e, LabelingMessage::Perform(Operation::Finish(entry))),
+                        };
                     }
+                    Ok(None) => Ok(LabelingMessage::Collided(planned.clone()).into()),
+                    Err(e) => Err(e),
+                },
+                Operation::Finish(entry) | Operation::Apply(entry) => {
+                    carry_out(entry, &output_dir, false)
+                        .map(|()| LabelingMessage::Applied(entry.clone()).into())
+                }
+                Operation::Revert(entry) => carry_out(entry, &output_dir, true)
+                    .map(|()| LabelingMessage::Reverted(entry.clone()).into()),
+            };
+
+            result.unwrap_or_else(|e| fail(e, LabelingMessage::Perform(operation)))
+        },
+        |out| out,
+    )
+}
+
+/// Applies or reverts `entry`, unless a failed attempt at it can be finished instead
+fn carry_out(entry: &JournalEntry, output_dir: &Path, revert: bool) -> Result<(), Error> {
+    if entry.settle(output_dir, revert)? {
+        return Ok(());
+    }
+
+    if revert {
+        entry.revert(output_dir)
+    } else {
+        entry.apply(output_dir)
+    }
+}
+
+fn handle_key(
+    shared: &SharedState,
+    local: &LabelingState,
+    key: keyboard::Key,
+    modifiers: keyboard::Modifiers,
+    captured: bool,
+) -> Task<Message> {
+    if local.current_image.is_none() {
+        return Task::none();
+    }
+
+    let message = if modifiers.command() {
+        // Shortcuts with modifiers also work while typing a prompt
+        match key.as_ref() {
+            keyboard::Key::Character(c) if c.eq_ignore_ascii_case("z") && modifiers.shift() => {
+                Some(LabelingMessage::Redo)
+            }
+            keyboard::Key::Character(c) if c.eq_ignore_ascii_case("z") => {
+                Some(LabelingMessage::Undo)
+            }
+            keyboard::Key::Character(c) if c.eq_ignore_ascii_case("y") => {
+                Some(LabelingMessage::Redo)
+            }
+            _ => None,
+        }
+    } else if captured || modifiers.alt() || modifiers.logo() {
+        None
+    } else if let Some(class_index) = shared
+        .key_bindings
+        .class_for(&key)
+        .filter(|index| *index < shared.dataset.classes.len())
+    {
+        Some(LabelingMessage::SubmitLabel(class_index))
+    } else if shared.key_bindings.is_trash(&key) {
+        Some(LabelingMessage::TrashCurrent)
+    } else if shared.key_bindings.is_skip(&key) {
+        Some(LabelingMessage::Skip)
+    } else {
+        match key.as_ref() {
+            keyboard::Key::Named(keyboard::key::Named::Enter) => {
+                shared.default_class.map(LabelingMessage::SubmitLabel)
+            }
+            keyboard::Key::Named(keyboard::key::Named::Escape) => {
+                return Task::done(Message::GoOptions);
+            }
+            keyboard::Key::Named(keyboard::key::Named::ArrowLeft) => {
+                Some(LabelingMessage::Previous)
+            }
+            keyboard::Key::Named(keyboard::key::Named::ArrowRight) => Some(LabelingMessage::Next),
+            _ => None,
+        }
+    };
+
+    message.map_or(Task::none(), |message| Task::done(message.into()))
+}
+
+pub fn subscription() -> Subscription<Message> {
+    event::listen_with(|event, status, _window| match event {
+        Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. }) => Some(
+            LabelingMessage::KeyPressed(key, modifiers, status == event::Status::Captured).into(),
+        ),
+        _ => None,
+    })
+}
+
+/// Completions shown below the tag being typed
+const SUGGESTIONS: usize = 8;
+
+/// Formats `n` with thousands separators
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i != 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
 
-                    destination_path.set_extension("txt");
-                    if let Err(e) = std::fs::write(&destination_path, prompt) {
-                        return Message::FatalError(format!(
-                            "Failed to write prompt to {destination_path:#?}: {e}"
-                        ));
+    out
+}
+
+/// Editor of the caption field at `index`, with its line and character counts
+fn caption_editor<'a>(
+    shared: &SharedState,
+    index: usize,
+    field: &str,
+    editor: &'a CaptionEditor,
+) -> Element<'a, Message> {
+    let submit = shared.key_bindings.submit;
+    let default_class = shared.default_class;
+    let caption = editor.text();
+    let max_token_length = shared.dataset.max_token_length;
+    let tokens = tokens::longest_line(&caption);
+
+    let (input, counts) = if shared.tag_mode {
+        let count = tags::split(&caption).len();
+
+        (
+            tag_editor(shared, index, field, editor),
+            format!(
+                "{} {}",
+                thousands(count),
+                if count == 1 { "tag" } else { "tags" }
+            ),
+        )
+    } else {
+        let lines = editor.content.line_count();
+        let characters = caption.chars().count();
+
+        (
+            text_editor(&editor.content)
+                .placeholder(format!(
+                    "{field} ({submit} submits default class, {} inserts a newline, Esc leaves the prompt)",
+                    submit.newline()
+                ))
+                .wrapping(Wrapping::Word)
+                .highlight_with::<TruncationHighlighter>(max_token_length, |_, theme: &Theme| {
+                    highlighter::Format {
+                        color: Some(theme.palette().danger),
+                        font: None,
+                    }
+                })
+                .on_action(move |action| LabelingMessage::EditPrompt(index, action).into())
+                .key_binding(move |press| {
+                    if press.status == text_editor::Status::Focused
+                        && submit.matches(&press.key, press.modifiers)
+                    {
+                        return default_class.map(|class| {
+                            Binding::Custom(LabelingMessage::SubmitLabel(class).into())
+                        });
                     }
 
-                    LabelingMessage::NextImage.into()
-                },
-                |out| out,
-            );
+                    Binding::from_key_press(press)
+                })
+                .into(),
+            format!(
+                "{} {}, {} {}",
+                thousands(lines),
+                if lines == 1 { "line" } else { "lines" },
+                thousands(characters),
+                if characters == 1 {
+                    "character"
+                } else {
+                    "characters"
+                }
+            ),
+        )
+    };
+
+    column![
+        input,
+        row![
+            text(counts).size(12).style(text::secondary),
+            horizontal_space(),
+            text(if tokens > max_token_length {
+                format!(
+                    "{tokens} / {max_token_length} CLIP tokens, the highlighted part is ignored"
+                )
+            } else {
+                format!("{tokens} / {max_token_length} CLIP tokens")
+            })
+            .size(12)
+            .style(if tokens > max_token_length {
+                text::danger
+            } else {
+                text::secondary
+            }),
+        ],
+    ]
+    .spacing(2)
+    .into()
+}
+
+/// Tags of the caption field at `index` as chips that remove them, followed by the tag being
+/// typed and what it may complete to
+fn tag_editor<'a>(
+    shared: &SharedState,
+    index: usize,
+    field: &str,
+    editor: &'a CaptionEditor,
+) -> Element<'a, Message> {
+    let caption = tags::split(&editor.text());
+    // Tag mode keeps the caption on one line, so the chips past the cut are those it falls in
+    let cut = tokens::truncation(&tags::join(&caption), shared.dataset.max_token_length);
+
+    let mut offset = 0;
+    let chips = caption.iter().enumerate().map(|(tag_index, tag)| {
+        let truncated = cut.is_some_and(|cut| offset + tag.len() > cut);
+        offset += tag.len() + ", ".len();
+
+        button(text(format!("{tag} ×")))
+            .style(if truncated {
+                button::danger
+            } else {
+                button::secondary
+            })
+            .on_press(LabelingMessage::RemoveTag(index, tag_index).into())
+            .into()
+    });
+
+    let suggestions = editor.suggestions.iter().map(|suggestion| {
+        button(text(&suggestion.label))
+            .style(button::text)
+            .on_press(LabelingMessage::AddSuggestion(index, suggestion.tag.clone()).into())
+            .into()
+    });
+
+    column![
+        row(chips).spacing(5).wrap(),
+        text_input(
+            &format!(
+                "{field} (a comma or Enter adds the tag, Enter without a tag submits default class, Esc leaves the prompt)"
+            ),
+            &editor.tag
+        )
+        .on_input(move |typed| LabelingMessage::SetTagInput(index, typed).into())
+        .on_submit(LabelingMessage::AddTag(index).into()),
+        row(suggestions).spacing(5).wrap(),
+    ]
+    .spacing(5)
+    .into()
+}
+
+/// What the current image was generated with, next to it
+fn generation_panel(generation: &Generation) -> Element<'_, Message> {
+    let heading = |label| {
+        text(label).font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        })
+    };
+    let mut details = column![
+        row![
+            heading("Prompt"),
+            horizontal_space(),
+            button("Use as Prompt").on_press(LabelingMessage::UseGenerationPrompt.into()),
+        ]
+        .align_y(Center),
+        text(&generation.prompt),
+    ]
+    .spacing(5);
+
+    let fields = [
+        ("Negative Prompt", generation.negative_prompt.as_ref()),
+        ("Seed", generation.seed.as_ref()),
+        ("Model", generation.model.as_ref()),
+    ];
+    for (label, value) in fields {
+        if let Some(value) = value {
+            details = details.push(heading(label)).push(text(value));
         }
     }
-    Task::none()
+    for (name, value) in &generation.settings {
+        details = details.push(text(format!("{name}: {value}")).style(text::secondary));
+    }
+
+    scrollable(details).width(300).into()
 }
 
 pub fn view<'a>(shared: &'a SharedState, local: &'a LabelingState) -> Element<'a, Message> {
+    let deferred = local.queue.count(ImageStatus::Deferred);
+    let total = local.queue.labeled() + local.queue.trashed() + local.queue.open();
+
     column![
         row![
+            button("Undo").on_press_maybe(
+                (!local.undo_stack.is_empty()).then_some(LabelingMessage::Undo.into())
+            ),
+            button("Redo").on_press_maybe(
+                (!local.redo_stack.is_empty()).then_some(LabelingMessage::Redo.into())
+            ),
+            text(format!(
+                "{} / {} labeled, {} trashed, {} deferred",
+                thousands(local.queue.labeled()),
+                thousands(total),
+                thousands(local.queue.trashed()),
+                thousands(deferred),
+            )),
             horizontal_space(),
-            button("Options").on_press(Message::GoOptions)
-        ],
-        text_input("Prompt", &local.input_prompt)
-            .on_input(|input| LabelingMessage::SetPrompt(input).into()),
-        row(shared.classes.iter().enumerate().map(|(index, class)| {
-            button(class.label.as_str())
-                .on_press(LabelingMessage::SubmitLabel(index).into())
-                .into()
-        }))
+            button("[Esc] Options").on_press(Message::GoOptions)
+        ]
+        .spacing(5)
+        .align_y(Center),
+        row![
+            button("[←] Previous").on_press(LabelingMessage::Previous.into()),
+            text(format!(
+                "Image {} of {}",
+                thousands(local.queue.cursor() + 1),
+                thousands(local.queue.len())
+            )),
+            button("Next [→]").on_press(LabelingMessage::Next.into()),
+            text_input("Go to # or file name", &local.goto_input)
+                .on_input(|input| LabelingMessage::SetGoTo(input).into())
+                .on_submit(LabelingMessage::GoTo.into()),
+        ]
+        .spacing(5)
+        .align_y(Center),
+        column(
+            shared
+                .dataset
+                .caption_fields()
+                .into_iter()
+                .zip(&local.input_prompts)
+                .enumerate()
+                .map(|(index, (field, editor))| caption_editor(shared, index, &field, editor))
+        )
         .spacing(5),
-        button("Trash").on_press(LabelingMessage::TrashCurrent.into()),
-        container(if let Some(path) = &local.current_image {
-            Element::from(image(path))
+        row(shared
+            .dataset
+            .classes
+            .iter()
+            .enumerate()
+            .map(|(index, class)| {
+                let mut label = class.label.clone();
+                if let Some(key) = shared.key_bindings.class_key(index) {
+                    label = format!("[{key}] {label}");
+                }
+                if shared.default_class == Some(index) {
+                    label.push_str(" [Enter]");
+                }
+
+                button(text(label))
+                    .on_press(LabelingMessage::SubmitLabel(index).into())
+                    .into()
+            }))
+        .spacing(5),
+        row![
+            button(text(format!("[{}] Trash", shared.key_bindings.trash)))
+                .on_press(LabelingMessage::TrashCurrent.into()),
+            button(text(format!("[{}] Skip", shared.key_bindings.skip)))
+                .on_press(LabelingMessage::Skip.into()),
+            horizontal_space(),
+            button(text(format!("Revisit Deferred ({deferred})"))).on_press_maybe(
+                (!local.queue.is_revisiting() && deferred != 0)
+                    .then_some(LabelingMessage::RevisitDeferred.into())
+            ),
+        ]
+        .spacing(5),
+        if let Some(failure) = &local.failure {
+            let skippable = matches!(
+                *failure.retry,
+                LabelingMessage::Perform(Operation::Move(..) | Operation::Finish(..))
+            );
+
+            Element::from(
+                row![
+                    text(failure.error.to_string()).style(text::danger),
+                    horizontal_space(),
+                    button("Retry")
+                        .on_press(LabelingMessage::ResolveFailure(FailureChoice::Retry).into()),
+                    button("Skip Image").on_press_maybe(skippable.then_some(
+                        LabelingMessage::ResolveFailure(FailureChoice::SkipImage).into()
+                    )),
+                    button("Abort")
+                        .on_press(LabelingMessage::ResolveFailure(FailureChoice::Abort).into()),
+                ]
+                .spacing(5)
+                .align_y(Center),
+            )
+        } else if let Some(planned) = &local.collision {
+            Element::from(
+                row![
+                    text(format!(
+                        "{:?} already exists in the destination.",
+                        planned.source.file_name().unwrap_or_default()
+                    )),
+                    horizontal_space(),
+                    button("Overwrite").on_press(
+                        LabelingMessage::ResolveCollision(Some(CollisionPolicy::Overwrite)).into()
+                    ),
+                    button("Keep Both").on_press(
+                        LabelingMessage::ResolveCollision(Some(CollisionPolicy::AutoSuffix)).into()
+                    ),
+                    button("Cancel").on_press(LabelingMessage::ResolveCollision(None).into()),
+                ]
+                .spacing(5)
+                .align_y(Center),
+            )
+        } else if let Some(warning) = &local.warning {
+            text(warning).style(text::danger).into()
         } else {
-            text("Loading...").into()
-        })
-        .width(Length::Fill)
-        .align_x(Center),
+            horizontal_space().into()
+        },
+        row![
+            container(if let Some(path) = &local.current_image {
+                Element::from(image(path))
+            } else {
+                text("Loading...").into()
+            })
+            .width(Length::Fill)
+            .align_x(Center),
+        ]
+        .push_maybe(local.generation.as_ref().map(generation_panel))
+        .spacing(10),
     ]
     .spacing(5)
     .into()
@@ -1,15 +1,64 @@
+use std::path::PathBuf;
+
 use iced::{
+    Alignment::Center,
     Element, Font, Task,
     font::Weight,
-    widget::{button, column, horizontal_space, row, text, text_input},
+    widget::{
+        button, checkbox, column, horizontal_space, pick_list, row, scrollable, text, text_input,
+    },
+};
+use quicklabel::{
+    CaptionOutput, Class, ClassError, Dataset, Error, Scan, TRIGGER_PLACEHOLDER,
+    collision::CollisionPolicy, tags::TagStyle, template::PLACEHOLDERS, tokens, xmp::XmpSink,
 };
+use rfd::FileDialog;
 
-use crate::{Class, Message, SharedState};
+use crate::{Message, SharedState, keybindings::SubmitShortcut, project::Project};
 
 #[derive(Debug, Clone)]
 pub struct OptionsState {
     class_input_label: String,
     class_input_repeats: usize,
+    class_input_trigger: String,
+    /// Why labeling couldn't begin
+    pub error: Option<String>,
+    /// What is in the output root
+    pub existing: Scan,
+    /// A scan of the output root is running, so `existing` may be out of date
+    pub scanning: bool,
+    /// Scans started so far, so only the result of the latest one is used
+    scans: u64,
+    /// Class directories are being renamed or merged, so the classes can't be changed meanwhile
+    pub moving: bool,
+    editing: Option<ClassEdit>,
+    /// Change waiting for the user to confirm it
+    confirmation: Option<Confirmation>,
+    /// Image the pre-filled prompt is previewed for
+    sample_image: Option<PathBuf>,
+    /// The pre-filled prompt rendered for the sample image, or why it can't be
+    prompt_preview: Option<Result<String, String>>,
+    /// Sidecar extensions as typed, comma separated
+    sidecar_input: String,
+    output_input_extension: String,
+    output_input_field: String,
+}
+
+/// Class being edited, with the label, repeats and trigger typed so far
+#[derive(Debug, Clone)]
+struct ClassEdit {
+    index: usize,
+    label: String,
+    repeats: usize,
+    trigger: String,
+}
+
+#[derive(Debug, Clone)]
+enum Confirmation {
+    /// Remove the class at the index, whose directory holds images
+    Delete(usize),
+    /// Merge the first class into the second
+    Merge(usize, usize),
 }
 
 impl Default for OptionsState {
@@ -17,83 +66,263 @@ impl Default for OptionsState {
         Self {
             class_input_label: String::new(),
             class_input_repeats: 1,
+            class_input_trigger: String::new(),
+            error: None,
+            existing: Scan::default(),
+            scanning: false,
+            scans: 0,
+            moving: false,
+            editing: None,
+            confirmation: None,
+            sample_image: None,
+            prompt_preview: None,
+            sidecar_input: String::new(),
+            output_input_extension: String::new(),
+            output_input_field: String::new(),
+        }
+    }
+}
+
+impl OptionsState {
+    /// Options of `shared`, and the task finding out about the class directories already in its
+    /// output root
+    pub fn new(shared: &SharedState) -> (Self, Task<Message>) {
+        let mut state = Self {
+            sidecar_input: shared.dataset.sidecar_extensions.join(", "),
+            ..Default::default()
+        };
+        state.preview_prompt(shared);
+        // The input directory is listed along with the output root, for an image to preview with
+        let scan = state.scan(shared, true);
+
+        (state, scan)
+    }
+
+    fn preview_prompt(&mut self, shared: &SharedState) {
+        self.prompt_preview = match shared.prefill_template() {
+            Ok(Some(template)) => Some(match &self.sample_image {
+                Some(image) => template
+                    .render(image)
+                    .map(|prompt| {
+                        format!(
+                            "Preview for {:?}: {prompt}",
+                            image.file_name().unwrap_or_default()
+                        )
+                    })
+                    .map_err(|e| e.to_string()),
+                None => Ok("No image in the input directory to preview with".into()),
+            }),
+            Ok(None) => None,
+            Err(e) => Some(Err(e.to_string())),
+        };
+    }
+
+    /// Scans the output root in the background, as it may hold a lot of images
+    fn rescan(&mut self, shared: &SharedState) -> Task<Message> {
+        self.scan(shared, false)
+    }
+
+    /// Scans the output root, and with `sample` picks the image to preview the prompt with too
+    fn scan(&mut self, shared: &SharedState, sample: bool) -> Task<Message> {
+        self.scans += 1;
+        self.scanning = true;
+
+        let id = self.scans;
+        let dataset = shared.dataset.clone();
+        Task::perform(
+            async move {
+                let sample_image = sample.then(|| {
+                    dataset
+                        .index()
+                        .ok()
+                        .and_then(|images| images.into_iter().next())
+                });
+
+                (dataset.scan(), sample_image)
+            },
+            move |(scan, sample_image)| OptionsMessage::Scanned(id, scan, sample_image).into(),
+        )
+    }
+
+    /// Renames or merges class directories with `change` in the background, as they may hold a
+    /// lot of images. `default_class` is the class submitted with Enter once that worked.
+    fn move_files(
+        &mut self,
+        shared: &SharedState,
+        default_class: Option<usize>,
+        change: impl FnOnce(&mut Dataset) -> Result<(), Error> + Send + 'static,
+    ) -> Task<Message> {
+        self.moving = true;
+
+        let mut dataset = shared.dataset.clone();
+        Task::perform(
+            async move { change(&mut dataset).map(|()| dataset.classes) },
+            move |classes| OptionsMessage::Moved(classes, default_class).into(),
+        )
+    }
+
+    /// Images already in the directory of `class`, unless they are still being counted
+    fn image_count(&self, class: &Class) -> Option<usize> {
+        if self.scanning {
+            return None;
         }
+
+        Some(
+            self.existing
+                .classes
+                .iter()
+                .find(|dir| dir.class.same_dir(class))
+                .map_or(0, |dir| dir.images.len()),
+        )
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum OptionsMessage {
-    /// (class label, repeats)
-    AddClass((String, usize)),
+    /// (class label, repeats, trigger)
+    AddClass((String, usize, String)),
+    /// Asks for confirmation first if the directory of the class holds images
     DeleteClass(usize),
+    /// Start editing the class at the index, or None to stop
+    EditClass(Option<usize>),
+    /// Apply the edit, renaming the directory of the class
+    SaveClass,
+    SwapClasses(usize, usize),
+    /// Merge the first class into the second, after asking for confirmation
+    MergeClass(usize, usize),
+    /// Carry out the change waiting for confirmation, or drop it
+    Confirm(bool),
+    /// Class submitted with Enter while labeling
+    SetDefaultClass(Option<usize>),
+    SetCollisionPolicy(CollisionPolicy),
+    SetManualTriggers(bool),
+    SetMergeSidecars(bool),
+    SetGenerationPrefill(bool),
+    SetStripPromptSyntax(bool),
+    SetXmpSink(XmpSink),
+    SetXmpPrefill(bool),
+    SetSubmitShortcut(SubmitShortcut),
+    SetMaxTokenLength(usize),
+    SetTagMode(bool),
+    SetTagStyle(TagStyle),
+    OpenTagListChooser,
+    /// None to complete only from the tags in the dataset
+    SetTagList(Option<PathBuf>),
+    /// (extension, field)
+    AddCaptionOutput((String, String)),
+    RemoveCaptionOutput(usize),
     InputChange((InputKind, String)),
+    /// (scan number, result) of scanning the output root, and the image to preview the prompt
+    /// with if the scan looked for one
+    Scanned(u64, Result<Scan, Error>, Option<Option<PathBuf>>),
+    /// (classes, class submitted with Enter) once class directories were renamed or merged
+    Moved(Result<Vec<Class>, Error>, Option<usize>),
 }
 
 #[derive(Debug, Clone)]
 pub enum InputKind {
     ClassLabel,
     ClassRepeats,
+    ClassTrigger,
+    FilenamePattern,
+    SidecarExtensions,
+    OutputExtension,
+    OutputField,
+    EditLabel,
+    EditRepeats,
+    EditTrigger,
     PromptPrefill,
+    ClassKeys,
+    TrashKey,
+    SkipKey,
 }
 
-impl Into<Message> for OptionsMessage {
-    fn into(self) -> Message {
-        Message::Options(self)
+impl From<OptionsMessage> for Message {
+    fn from(message: OptionsMessage) -> Self {
+        Message::Options(message)
     }
 }
 
 pub fn view<'a>(shared: &'a SharedState, local: &'a OptionsState) -> Element<'a, Message> {
-    column![
+    let new_class = Class::validated(
+        &local.class_input_label,
+        local.class_input_repeats,
+        &shared.dataset.classes,
+    );
+    let new_output = CaptionOutput::validated(
+        &local.output_input_extension,
+        &local.output_input_field,
+        &shared.dataset.caption_outputs,
+    );
+
+    let options = column![
         row![
             text("Classes").font(Font {
                 weight: Weight::Bold,
                 ..Default::default()
             }),
             horizontal_space(),
-            button("Label").on_press_maybe(if shared.classes.len() != 0 {
-                Some(Message::GoLabel)
-            } else {
-                None
-            })
+            button("Label").on_press_maybe(
+                (!shared.dataset.classes.is_empty() && !local.moving).then_some(Message::GoLabel)
+            )
         ],
+        text(local.error.as_deref().unwrap_or_default()).style(text::danger),
+        if local.moving {
+            text("Moving images...").into()
+        } else if let Some(question) = local
+            .confirmation
+            .as_ref()
+            .and_then(|confirmation| question(shared, local, confirmation))
+        {
+            Element::from(
+                row![
+                    text(question),
+                    horizontal_space(),
+                    button("Confirm").on_press(OptionsMessage::Confirm(true).into()),
+                    button("Cancel").on_press(OptionsMessage::Confirm(false).into()),
+                ]
+                .spacing(10)
+                .align_y(Center),
+            )
+        } else {
+            horizontal_space().into()
+        },
         row![
             text_input("Class Name", &local.class_input_label)
                 .on_input(|x| OptionsMessage::InputChange((InputKind::ClassLabel, x)).into()),
             text_input("Repeats", &local.class_input_repeats.to_string())
                 .on_input(|x| OptionsMessage::InputChange((InputKind::ClassRepeats, x)).into()),
-            button("Add").on_press_maybe(
-                if local.class_input_label.len() != 0 && local.class_input_repeats != 0 {
-                    Some(
-                        OptionsMessage::AddClass((
-                            local.class_input_label.clone(),
-                            local.class_input_repeats,
-                        ))
-                        .into(),
-                    )
-                } else {
-                    None
-                }
-            )
-        ]
-        .spacing(10),
-        if shared.classes.len() != 0 {
-            column(shared.classes.iter().enumerate().map(|(index, set)| {
-                row![
-                    text(format!(
-                        "{} ({} repeats)",
-                        &set.label,
-                        set.repeats.to_string()
-                    )),
-                    button("Remove").on_press(OptionsMessage::DeleteClass(index).into())
-                ]
-                .spacing(10)
+            text_input("Trigger (optional)", &local.class_input_trigger)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::ClassTrigger, x)).into()),
+            button("Add").on_press_maybe((new_class.is_ok() && !local.moving).then(|| {
+                OptionsMessage::AddClass((
+                    local.class_input_label.clone(),
+                    local.class_input_repeats,
+                    local.class_input_trigger.clone(),
+                ))
                 .into()
-            }))
+            })),
+            preview(&new_class, !local.class_input_label.is_empty()),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        if !shared.dataset.classes.is_empty() {
+            column(
+                shared
+                    .dataset
+                    .classes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, set)| class_row(shared, local, index, set)),
+            )
             .spacing(5)
             .into()
         } else {
             Element::from(text("None"))
         },
+        column(warnings(shared, local).into_iter().map(|warning| {
+            text(warning).style(text::danger).into()
+        })),
         text("Pre-filled Prompt").font(Font {
             weight: Weight::Bold,
             ..Default::default()
@@ -102,12 +331,415 @@ pub fn view<'a>(shared: &'a SharedState, local: &'a OptionsState) -> Element<'a,
             "Prompt",
             shared.prompt_prefill.as_ref().unwrap_or(&String::new())
         )
-        .on_input(|x| OptionsMessage::InputChange((InputKind::PromptPrefill, x)).into())
+        .on_input(|x| OptionsMessage::InputChange((InputKind::PromptPrefill, x)).into()),
+        text_input(
+            "File Name Pattern (optional regex, its groups become {1} or {name})",
+            shared.filename_pattern.as_ref().unwrap_or(&String::new())
+        )
+        .on_input(|x| OptionsMessage::InputChange((InputKind::FilenamePattern, x)).into()),
+        match &local.prompt_preview {
+            Some(Ok(preview)) => Element::from(text(preview).style(text::secondary)),
+            Some(Err(e)) => text(e).style(text::danger).into(),
+            None => horizontal_space().into(),
+        },
+        text(format!(
+            "Placeholders: {{{}}}, {{exif.DateTimeOriginal}} and other EXIF tags. Write {{{{ and }}}} for literal braces.",
+            PLACEHOLDERS.join("}, {")
+        )),
+        text(format!(
+            "The trigger of the submitted class replaces {TRIGGER_PLACEHOLDER} in the prompt, or goes in front of it if the prompt doesn't mention it."
+        )),
+        checkbox(
+            "Don't insert triggers, only warn when the prompt doesn't mention one",
+            shared.manual_triggers
+        )
+        .on_toggle(|checked| OptionsMessage::SetManualTriggers(checked).into()),
+        text("Caption Files").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        column(
+            shared
+                .dataset
+                .caption_outputs
+                .iter()
+                .enumerate()
+                .map(|(index, output)| {
+                    row![
+                        text(output.to_string()),
+                        button("Remove").on_press_maybe(
+                            (shared.dataset.caption_outputs.len() > 1)
+                                .then_some(OptionsMessage::RemoveCaptionOutput(index).into())
+                        ),
+                    ]
+                    .spacing(10)
+                    .align_y(Center)
+                    .into()
+                })
+        )
+        .spacing(5),
+        row![
+            text_input("Extension", &local.output_input_extension)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::OutputExtension, x)).into()),
+            text_input("Field", &local.output_input_field)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::OutputField, x)).into()),
+            button("Add").on_press_maybe(new_output.is_ok().then(|| {
+                OptionsMessage::AddCaptionOutput((
+                    local.output_input_extension.clone(),
+                    local.output_input_field.clone(),
+                ))
+                .into()
+            })),
+            match &new_output {
+                Err(e) if !local.output_input_extension.is_empty() => {
+                    Element::from(text(e.to_string()).style(text::danger))
+                }
+                _ => horizontal_space().into(),
+            },
+        ]
+        .spacing(10)
+        .align_y(Center),
+        text("Every caption file is written next to labeled images. Each field gets its own input while labeling, files with the same field get the same text. The pre-filled prompt goes into the first field."),
+        text("Sidecar Captions").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        row![
+            text("Extensions"),
+            text_input("txt, caption", &local.sidecar_input).on_input(|x| {
+                OptionsMessage::InputChange((InputKind::SidecarExtensions, x)).into()
+            }),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        checkbox(
+            "Put the pre-filled prompt in front of sidecar captions and generation prompts, rather than replacing it",
+            shared.merge_sidecars
+        )
+        .on_toggle(|checked| OptionsMessage::SetMergeSidecars(checked).into()),
+        text("Captions next to the images in the input directory pre-fill the prompt. When images are moved, their sidecars are removed once labeled, and go along when trashed."),
+        text("Generated Images").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        checkbox(
+            "Pre-fill the prompt an image was generated with, unless it has a sidecar caption",
+            shared.generation_prefill
+        )
+        .on_toggle(|checked| OptionsMessage::SetGenerationPrefill(checked).into()),
+        checkbox(
+            "Strip LoRA tags like <lora:name:0.8> and weights like (word:1.2) from generation prompts",
+            shared.strip_prompt_syntax
+        )
+        .on_toggle(|checked| OptionsMessage::SetStripPromptSyntax(checked).into()),
+        text("XMP Metadata").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        pick_list(XmpSink::ALL, Some(shared.dataset.xmp), |sink| {
+            OptionsMessage::SetXmpSink(sink).into()
+        }),
+        checkbox(
+            "Pre-fill the description and keywords of XMP metadata, unless the image has a sidecar caption",
+            shared.xmp_prefill
+        )
+        .on_toggle(|checked| OptionsMessage::SetXmpPrefill(checked).into()),
+        text("The caption is written as the description, and the class as a keyword, for photo managers to see. Embedding needs JPEG, PNG or WebP images that are moved or copied, otherwise a .xmp sidecar is written."),
+        text("Caption Length").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        row![
+            text("Max Token Length"),
+            pick_list(
+                tokens::MAX_TOKEN_LENGTHS,
+                Some(shared.dataset.max_token_length),
+                |max| OptionsMessage::SetMaxTokenLength(max).into()
+            ),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        text("Match the trainer's --max_token_length. CLIP tokens of each caption line past it are ignored during training, and highlighted while typing."),
+        text("Booru Tags").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        checkbox(
+            "Edit captions as comma-separated tags, like Danbooru tags",
+            shared.tag_mode
+        )
+        .on_toggle(|checked| OptionsMessage::SetTagMode(checked).into()),
+        row![
+            text("Separate Words With"),
+            pick_list(TagStyle::ALL, Some(shared.tag_style), |style| {
+                OptionsMessage::SetTagStyle(style).into()
+            }),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        row![
+            text("Tag List"),
+            text(
+                shared
+                    .tag_list
+                    .as_ref()
+                    .map_or("None".into(), |path| path.to_string_lossy())
+            ),
+            button("Choose").on_press(OptionsMessage::OpenTagListChooser.into()),
+            button("Clear").on_press_maybe(
+                shared
+                    .tag_list
+                    .is_some()
+                    .then_some(OptionsMessage::SetTagList(None).into())
+            ),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        text("Tags are completed from the tags already in the dataset, and from a CSV like the danbooru.csv of the tag autocomplete extension, with the name, category, post count and aliases of a tag on every line. Aliases are replaced by the tag they stand for."),
+        text("File Name Collisions").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        pick_list(
+            CollisionPolicy::ALL,
+            Some(shared.dataset.collision_policy),
+            |policy| OptionsMessage::SetCollisionPolicy(policy).into()
+        ),
+        text("Key Bindings").font(Font {
+            weight: Weight::Bold,
+            ..Default::default()
+        }),
+        row![
+            text("Classes"),
+            text_input("Class Keys", &shared.key_bindings.class_keys)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::ClassKeys, x)).into()),
+            text("Trash"),
+            text_input("Trash", &shared.key_bindings.trash.to_string())
+                .on_input(|x| OptionsMessage::InputChange((InputKind::TrashKey, x)).into()),
+            text("Skip"),
+            text_input("Skip", &shared.key_bindings.skip.to_string())
+                .on_input(|x| OptionsMessage::InputChange((InputKind::SkipKey, x)).into()),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        row![
+            text("Submit While Typing"),
+            pick_list(
+                SubmitShortcut::ALL,
+                Some(shared.key_bindings.submit),
+                |shortcut| OptionsMessage::SetSubmitShortcut(shortcut).into()
+            ),
+        ]
+        .spacing(10)
+        .align_y(Center),
+        text(format!(
+            "Keys only apply while the prompt isn't focused. Enter submits the default class, Esc leaves the prompt. While typing, {} submits and {} inserts a newline.",
+            shared.key_bindings.submit,
+            shared.key_bindings.submit.newline()
+        ))
+    ]
+    .spacing(10);
+
+    // With many classes and outputs, the options don't fit in the window
+    scrollable(options).into()
+}
+
+fn class_row<'a>(
+    shared: &'a SharedState,
+    local: &'a OptionsState,
+    index: usize,
+    class: &'a Class,
+) -> Element<'a, Message> {
+    let classes = &shared.dataset.classes;
+
+    if let Some(edit) = local.editing.as_ref().filter(|edit| edit.index == index) {
+        let others: Vec<_> = classes
+            .iter()
+            .filter(|other| *other != class)
+            .cloned()
+            .collect();
+        let edited = Class::validated(&edit.label, edit.repeats, &others);
+
+        return row![
+            text_input("Class Name", &edit.label)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::EditLabel, x)).into())
+                .on_submit(OptionsMessage::SaveClass.into()),
+            text_input("Repeats", &edit.repeats.to_string())
+                .on_input(|x| OptionsMessage::InputChange((InputKind::EditRepeats, x)).into())
+                .on_submit(OptionsMessage::SaveClass.into()),
+            text_input("Trigger (optional)", &edit.trigger)
+                .on_input(|x| OptionsMessage::InputChange((InputKind::EditTrigger, x)).into())
+                .on_submit(OptionsMessage::SaveClass.into()),
+            button("Save").on_press_maybe(
+                (edited.is_ok() && !local.moving).then_some(OptionsMessage::SaveClass.into())
+            ),
+            preview(&edited, true),
+            pick_list(others, None::<Class>, move |into| {
+                let into = classes.iter().position(|other| *other == into);
+                into.map_or(Message::NoOp, |into| {
+                    OptionsMessage::MergeClass(index, into).into()
+                })
+            })
+            .placeholder("Merge into..."),
+            button("Cancel").on_press(OptionsMessage::EditClass(None).into()),
+        ]
+        .spacing(10)
+        .into();
+    }
+
+    let is_default = shared.default_class == Some(index);
+    // Moving or removing classes would change what the pending question or the files being
+    // moved are about
+    let idle = local.confirmation.is_none() && !local.moving;
+    row![
+        text(match local.image_count(class) {
+            Some(images) => format!(
+                "{} ({} repeats, {images} images)",
+                class.label, class.repeats
+            ),
+            None => format!(
+                "{} ({} repeats, counting images...)",
+                class.label, class.repeats
+            ),
+        }),
+        text(
+            class
+                .trigger
+                .as_ref()
+                .map(|trigger| format!("Trigger: {trigger}"))
+                .unwrap_or_default()
+        )
+        .style(text::secondary),
+        horizontal_space(),
+        button("Edit").on_press_maybe(idle.then(|| OptionsMessage::EditClass(Some(index)).into())),
+        button("↑").on_press_maybe(
+            (idle && index != 0).then(|| OptionsMessage::SwapClasses(index, index - 1).into())
+        ),
+        button("↓").on_press_maybe(
+            (idle && index + 1 < classes.len()).then(|| OptionsMessage::SwapClasses(
+                index,
+                index + 1
+            )
+            .into())
+        ),
+        button(if is_default {
+            "Default"
+        } else {
+            "Make Default"
+        })
+        .on_press(OptionsMessage::SetDefaultClass((!is_default).then_some(index)).into()),
+        button("Remove").on_press_maybe(idle.then(|| OptionsMessage::DeleteClass(index).into()))
     ]
     .spacing(10)
+    .align_y(Center)
     .into()
 }
 
+/// Directory name the class will get, or why it can't be used
+fn preview<'a>(class: &Result<Class, ClassError>, show_error: bool) -> Element<'a, Message> {
+    match class {
+        Ok(class) => text(format!("Directory: {class}"))
+            .style(text::secondary)
+            .into(),
+        Err(e) if show_error => text(e.to_string()).style(text::danger).into(),
+        Err(..) => horizontal_space().into(),
+    }
+}
+
+/// What to ask about `confirmation`, unless its classes are gone
+fn question(
+    shared: &SharedState,
+    local: &OptionsState,
+    confirmation: &Confirmation,
+) -> Option<String> {
+    let classes = &shared.dataset.classes;
+
+    Some(match *confirmation {
+        Confirmation::Delete(index) => {
+            let class = classes.get(index)?;
+            format!(
+                "{:?} {}. Remove the class? Its directory stays on disk.",
+                class.label,
+                match local.image_count(class) {
+                    Some(images) => format!("holds {images} images"),
+                    None => "may hold images".into(),
+                }
+            )
+        }
+        Confirmation::Merge(from, into) => {
+            let (from, into) = (classes.get(from)?, classes.get(into)?);
+            format!(
+                "Move the {}images of {:?} into {:?}, and remove {:?}?",
+                local
+                    .image_count(from)
+                    .map(|images| format!("{images} "))
+                    .unwrap_or_default(),
+                from.to_string(),
+                into.to_string(),
+                from.label
+            )
+        }
+    })
+}
+
+/// Writes the classes to the project file, so it matches the directories on disk
+fn save_classes(shared: &SharedState) -> Result<(), Error> {
+    let path = Project::path(&shared.dataset.output_dir);
+    let progress = if path.exists() {
+        Project::load(&path)?.progress
+    } else {
+        Default::default()
+    };
+
+    Project {
+        session: shared.clone(),
+        progress,
+    }
+    .save()
+}
+
+/// Keeps the default class on the same class once the one at `index` is removed
+fn remove_class(state: &mut SharedState, index: usize) {
+    state.dataset.classes.remove(index);
+
+    state.default_class = match state.default_class {
+        Some(default) if default == index => None,
+        Some(default) if default > index => Some(default - 1),
+        default => default,
+    };
+}
+
+/// Class directories that won't end up the way the user probably expects
+fn warnings(shared: &SharedState, local: &OptionsState) -> Vec<String> {
+    let classes = &shared.dataset.classes;
+    let mut warnings = Vec::new();
+
+    for (index, class) in classes.iter().enumerate() {
+        let first = classes[..index]
+            .iter()
+            .all(|other| other.label != class.label);
+        if first
+            && classes[index + 1..]
+                .iter()
+                .any(|other| other.label == class.label)
+        {
+            warnings.push(format!(
+                "{:?} is used with different repeats, each gets its own directory",
+                class.label
+            ));
+        }
+    }
+
+    for path in &local.existing.unrecognized {
+        warnings.push(format!(
+            "{:?} in the output directory isn't named {{repeats}}_{{label}}, so it isn't a class",
+            path.file_name().unwrap_or_default()
+        ));
+    }
+
+    warnings
+}
+
 pub fn update(
     state: &mut SharedState,
     local: &mut OptionsState,
@@ -117,25 +749,294 @@ pub fn update(
         OptionsMessage::InputChange((kind, value)) => match kind {
             InputKind::ClassLabel => local.class_input_label = value,
             InputKind::ClassRepeats => {
-                if let Ok(value) = usize::from_str_radix(&value, 10) {
+                if let Ok(value) = value.parse() {
                     local.class_input_repeats = value
                 }
             }
+            InputKind::EditLabel => {
+                if let Some(edit) = &mut local.editing {
+                    edit.label = value;
+                }
+            }
+            InputKind::EditRepeats => {
+                if let (Some(edit), Ok(value)) = (&mut local.editing, value.parse()) {
+                    edit.repeats = value;
+                }
+            }
+            InputKind::ClassTrigger => local.class_input_trigger = value,
+            InputKind::EditTrigger => {
+                if let Some(edit) = &mut local.editing {
+                    edit.trigger = value;
+                }
+            }
             InputKind::PromptPrefill => {
-                if value.len() != 0 {
+                if !value.is_empty() {
                     state.prompt_prefill = Some(value);
                 } else {
                     state.prompt_prefill = None;
                 }
+                local.preview_prompt(state);
+            }
+            InputKind::SidecarExtensions => {
+                let mut extensions = Vec::new();
+                for extension in value.split(',') {
+                    let extension = extension.trim().trim_start_matches('.').to_string();
+                    if !extension.is_empty() && !extensions.contains(&extension) {
+                        extensions.push(extension);
+                    }
+                }
+
+                state.dataset.sidecar_extensions = extensions;
+                local.sidecar_input = value;
+            }
+            InputKind::OutputExtension => local.output_input_extension = value,
+            InputKind::OutputField => local.output_input_field = value,
+            InputKind::FilenamePattern => {
+                state.filename_pattern = (!value.is_empty()).then_some(value);
+                local.preview_prompt(state);
+            }
+            InputKind::ClassKeys => state.key_bindings.class_keys = value,
+            // Typing into a single key binding replaces it
+            InputKind::TrashKey => {
+                if let Some(key) = value.chars().last() {
+                    state.key_bindings.trash = key;
+                }
+            }
+            InputKind::SkipKey => {
+                if let Some(key) = value.chars().last() {
+                    state.key_bindings.skip = key;
+                }
             }
         },
 
-        OptionsMessage::AddClass((label, repeats)) => {
-            state.classes.push(Class { label, repeats });
+        OptionsMessage::AddClass((label, repeats, trigger)) => {
+            match Class::validated(&label, repeats, &state.dataset.classes) {
+                Ok(class) => {
+                    state.dataset.classes.push(class.with_trigger(&trigger));
+                    local.class_input_label.clear();
+                    local.class_input_trigger.clear();
+                }
+                Err(e) => local.error = Some(e.to_string()),
+            }
+        }
+
+        OptionsMessage::AddCaptionOutput((extension, field)) => {
+            match CaptionOutput::validated(&extension, &field, &state.dataset.caption_outputs) {
+                Ok(output) => {
+                    state.dataset.caption_outputs.push(output);
+                    local.output_input_extension.clear();
+                    return local.rescan(state);
+                }
+                Err(e) => local.error = Some(e.to_string()),
+            }
+        }
+
+        OptionsMessage::RemoveCaptionOutput(index) => {
+            if state.dataset.caption_outputs.len() > 1 {
+                state.dataset.caption_outputs.remove(index);
+                return local.rescan(state);
+            }
         }
 
         OptionsMessage::DeleteClass(index) => {
-            state.classes.remove(index);
+            local.confirmation = None;
+            if index >= state.dataset.classes.len() {
+                return Task::none();
+            }
+
+            // Images that are still being counted may be there, so better ask
+            if local.image_count(&state.dataset.classes[index]) != Some(0) {
+                local.confirmation = Some(Confirmation::Delete(index));
+            } else {
+                local.editing = None;
+                remove_class(state, index);
+            }
+        }
+
+        OptionsMessage::EditClass(index) => {
+            local.editing = index.map(|index| ClassEdit {
+                index,
+                label: state.dataset.classes[index].label.clone(),
+                repeats: state.dataset.classes[index].repeats,
+                trigger: state.dataset.classes[index]
+                    .trigger
+                    .clone()
+                    .unwrap_or_default(),
+            });
+        }
+
+        OptionsMessage::SaveClass => {
+            let Some(edit) = local.editing.clone().filter(|_| !local.moving) else {
+                return Task::none();
+            };
+
+            let others = state
+                .dataset
+                .classes
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != edit.index)
+                .map(|(_, class)| class);
+            let class = match Class::validated(&edit.label, edit.repeats, others) {
+                Ok(class) => class.with_trigger(&edit.trigger),
+                Err(e) => {
+                    local.error = Some(e.to_string());
+                    return Task::none();
+                }
+            };
+
+            return local.move_files(state, state.default_class, move |dataset| {
+                dataset.edit_class(edit.index, class)
+            });
+        }
+
+        OptionsMessage::SwapClasses(a, b) => {
+            local.editing = None;
+            local.confirmation = None;
+            state.dataset.classes.swap(a, b);
+
+            state.default_class = match state.default_class {
+                Some(default) if default == a => Some(b),
+                Some(default) if default == b => Some(a),
+                default => default,
+            };
+        }
+
+        OptionsMessage::MergeClass(from, into) => {
+            local.confirmation = Some(Confirmation::Merge(from, into));
+        }
+
+        OptionsMessage::Confirm(confirmed) => {
+            let Some(confirmation) = local.confirmation.take().filter(|_| confirmed) else {
+                return Task::none();
+            };
+            let count = state.dataset.classes.len();
+            let valid = match confirmation {
+                Confirmation::Delete(index) => index < count,
+                Confirmation::Merge(from, into) => from < count && into < count && from != into,
+            };
+            if !valid || local.moving {
+                return Task::none();
+            }
+            local.editing = None;
+
+            match confirmation {
+                Confirmation::Delete(index) => remove_class(state, index),
+                Confirmation::Merge(from, into) => {
+                    // Whatever was submitted with Enter goes to the merged class now
+                    let default_class = state
+                        .default_class
+                        .map(|default| if default == from { into } else { default })
+                        .map(|default| if default > from { default - 1 } else { default });
+
+                    return local.move_files(state, default_class, move |dataset| {
+                        dataset.merge_classes(from, into)
+                    });
+                }
+            }
+        }
+
+        OptionsMessage::Moved(classes, default_class) => {
+            local.moving = false;
+
+            let result = classes.and_then(|classes| {
+                state.dataset.classes = classes;
+                state.default_class = default_class;
+                local.editing = None;
+
+                save_classes(state)
+            });
+            local.error = result.err().map(|e| e.to_string());
+
+            return local.rescan(state);
+        }
+
+        OptionsMessage::Scanned(id, scan, sample_image) => {
+            // The input directory hasn't changed since, unlike the output root
+            if let Some(sample_image) = sample_image {
+                local.sample_image = sample_image;
+                local.preview_prompt(state);
+            }
+
+            // An older scan, whose result may not match the directories anymore
+            if id != local.scans {
+                return Task::none();
+            }
+
+            local.scanning = false;
+            match scan {
+                Ok(scan) => local.existing = scan,
+                Err(e) => local.error = Some(e.to_string()),
+            }
+        }
+
+        OptionsMessage::SetDefaultClass(index) => {
+            state.default_class = index;
+        }
+
+        OptionsMessage::SetCollisionPolicy(policy) => {
+            state.dataset.collision_policy = policy;
+        }
+
+        OptionsMessage::SetManualTriggers(manual) => {
+            state.manual_triggers = manual;
+        }
+
+        OptionsMessage::SetMergeSidecars(merge) => {
+            state.merge_sidecars = merge;
+        }
+
+        OptionsMessage::SetGenerationPrefill(prefill) => {
+            state.generation_prefill = prefill;
+        }
+
+        OptionsMessage::SetStripPromptSyntax(strip) => {
+            state.strip_prompt_syntax = strip;
+        }
+
+        OptionsMessage::SetXmpSink(sink) => {
+            state.dataset.xmp = sink;
+        }
+
+        OptionsMessage::SetXmpPrefill(prefill) => {
+            state.xmp_prefill = prefill;
+        }
+
+        OptionsMessage::SetSubmitShortcut(shortcut) => {
+            state.key_bindings.submit = shortcut;
+        }
+
+        OptionsMessage::SetMaxTokenLength(max) => {
+            state.dataset.max_token_length = max;
+        }
+
+        OptionsMessage::SetTagMode(tag_mode) => {
+            state.tag_mode = tag_mode;
+        }
+
+        OptionsMessage::SetTagStyle(style) => {
+            state.tag_style = style;
+        }
+
+        OptionsMessage::OpenTagListChooser => {
+            return Task::perform(
+                async {
+                    FileDialog::new()
+                        .add_filter("Tag list", &["csv"])
+                        .pick_file()
+                },
+                |path| {
+                    if let Some(path) = path {
+                        OptionsMessage::SetTagList(Some(path)).into()
+                    } else {
+                        Message::NoOp
+                    }
+                },
+            );
+        }
+
+        OptionsMessage::SetTagList(path) => {
+            state.tag_list = path;
         }
     }
 
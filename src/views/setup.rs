@@ -1,18 +1,25 @@
 use std::path::PathBuf;
 
 use iced::{
+    Alignment::Center,
     Element, Task,
-    widget::{button, column, horizontal_space, row, text, text_input},
+    widget::{button, column, horizontal_space, pick_list, row, text, text_input},
 };
+use quicklabel::{Error, fsops::TransferMode};
 use rfd::FileDialog;
 
-use crate::Message;
+use crate::{Message, project::Project};
 
 #[derive(Debug, Clone)]
 pub struct SetupState {
     pub input_dir: Option<PathBuf>,
     pub output_dir: Option<PathBuf>,
     pub trash_dir: Option<PathBuf>,
+    pub transfer_mode: TransferMode,
+    /// Project file of the most recent session, if any
+    pub last_session: Option<PathBuf>,
+    /// Why the project couldn't be opened
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -25,12 +32,16 @@ pub enum DirectoryKind {
 #[derive(Debug, Clone)]
 pub enum SetupMessage {
     SetPath(DirectoryKind, PathBuf),
+    SetTransferMode(TransferMode),
     OpenChooser(DirectoryKind),
+    OpenProjectChooser,
+    LoadProject(PathBuf),
+    LoadFailed(Error),
 }
 
-impl Into<Message> for SetupMessage {
-    fn into(self) -> Message {
-        Message::Setup(self)
+impl From<SetupMessage> for Message {
+    fn from(message: SetupMessage) -> Self {
+        Message::Setup(message)
     }
 }
 
@@ -38,7 +49,7 @@ fn opt_buf_to_string(buf: Option<PathBuf>) -> String {
     buf.map_or("".to_owned(), |x| x.to_string_lossy().to_string())
 }
 
-pub fn view(state: &SetupState) -> Element<Message> {
+pub fn view(state: &SetupState) -> Element<'_, Message> {
     column![
         column![
             text("Input Directory"),
@@ -64,7 +75,34 @@ pub fn view(state: &SetupState) -> Element<Message> {
             ]
             .spacing(10),
         ],
+        column![
+            text("Transfer Mode"),
+            row![
+                pick_list(TransferMode::ALL, Some(state.transfer_mode), |mode| {
+                    SetupMessage::SetTransferMode(mode).into()
+                }),
+                text(match state.transfer_mode {
+                    TransferMode::Move => "Images are moved out of the input directory.",
+                    TransferMode::Copy =>
+                        "Images are copied, the input directory is left untouched.",
+                    TransferMode::Hardlink =>
+                        "Images are hardlinked, or copied where input and output are on different filesystems.",
+                    TransferMode::Symlink =>
+                        "Images are symlinked, the input directory must stay where it is.",
+                }),
+            ]
+            .spacing(10)
+            .align_y(Center),
+        ],
         row![
+            button("Open Project").on_press(SetupMessage::OpenProjectChooser.into()),
+            button("Resume Last Session").on_press_maybe(
+                state
+                    .last_session
+                    .clone()
+                    .map(|path| SetupMessage::LoadProject(path).into())
+            ),
+            text(state.error.as_deref().unwrap_or_default()).style(text::danger),
             horizontal_space(),
             button("Begin").on_press_maybe(
                 if state.output_dir.is_some() && state.input_dir.is_some() {
@@ -74,6 +112,7 @@ pub fn view(state: &SetupState) -> Element<Message> {
                 }
             )
         ]
+        .spacing(10)
     ]
     .spacing(10)
     .into()
@@ -98,6 +137,41 @@ pub fn update(state: &mut SetupState, message: SetupMessage) -> Task<Message> {
             );
         }
 
+        SetupMessage::OpenProjectChooser => {
+            return Task::perform(
+                async {
+                    FileDialog::new()
+                        .add_filter("quicklabel project", &["toml"])
+                        .pick_file()
+                },
+                |path| {
+                    if let Some(path) = path {
+                        SetupMessage::LoadProject(path).into()
+                    } else {
+                        Message::NoOp
+                    }
+                },
+            );
+        }
+
+        SetupMessage::LoadProject(path) => {
+            return Task::perform(
+                async move { Project::load(&path) },
+                |project| match project {
+                    Ok(project) => Message::ResumeProject(project),
+                    Err(e) => SetupMessage::LoadFailed(e).into(),
+                },
+            );
+        }
+
+        SetupMessage::LoadFailed(e) => {
+            state.error = Some(e.to_string());
+        }
+
+        SetupMessage::SetTransferMode(mode) => {
+            state.transfer_mode = mode;
+        }
+
         SetupMessage::SetPath(kind, path) => match kind {
             DirectoryKind::Input => {
                 state.input_dir = Some(path);
@@ -0,0 +1,486 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, fsops, metadata};
+
+/// Identifies the APP1 segment of a JPEG holding XMP
+const JPEG_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Keyword of the `iTXt` chunk of a PNG holding XMP
+const PNG_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Flag in the `VP8X` chunk of a WebP telling that it has an `XMP ` chunk
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// Where captions are written as XMP, for photo managers like digiKam or darktable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum XmpSink {
+    #[default]
+    Off,
+    /// Into the image itself. Images that are linked rather than moved or copied get a sidecar
+    /// instead, so the original isn't touched
+    Embed,
+    /// Into `{image}.xmp` next to the image, e.g. `a.jpg.xmp`
+    Sidecar,
+}
+
+impl XmpSink {
+    pub const ALL: [XmpSink; 3] = [XmpSink::Off, XmpSink::Embed, XmpSink::Sidecar];
+}
+
+impl Display for XmpSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            XmpSink::Off => "Don't write XMP",
+            XmpSink::Embed => "Embed in the image",
+            XmpSink::Sidecar => "Write .xmp sidecar",
+        })
+    }
+}
+
+impl FromStr for XmpSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(XmpSink::Off),
+            "embed" => Ok(XmpSink::Embed),
+            "sidecar" => Ok(XmpSink::Sidecar),
+            _ => Err(format!(
+                "Unknown XMP sink {s:?}, expected one of off, embed or sidecar"
+            )),
+        }
+    }
+}
+
+/// The parts of XMP that describe an image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmpCaption {
+    /// `dc:description`
+    pub description: Option<String>,
+    /// `dc:subject`, the keywords
+    pub subjects: Vec<String>,
+}
+
+impl XmpCaption {
+    /// The description, or the keywords if there is none
+    pub fn caption(&self) -> Option<String> {
+        self.description
+            .clone()
+            .or_else(|| (!self.subjects.is_empty()).then(|| self.subjects.join(", ")))
+    }
+
+    pub fn parse(packet: &str) -> Self {
+        static DESCRIPTION: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"(?s)<dc:description\b[^>]*>(.*?)</dc:description>").unwrap()
+        });
+        static SUBJECT: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"(?s)<dc:subject\b[^>]*>(.*?)</dc:subject>").unwrap());
+        static ITEM: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"(?s)<rdf:li\b[^>]*>(.*?)</rdf:li>").unwrap());
+
+        let items = |list: &Regex| -> Vec<String> {
+            list.captures(packet)
+                .map(|list| {
+                    ITEM.captures_iter(list.get(1).unwrap().as_str())
+                        .map(|item| unescape(item[1].trim()))
+                        .filter(|item| !item.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            description: items(&DESCRIPTION).into_iter().next(),
+            subjects: items(&SUBJECT),
+        }
+    }
+
+    /// An XMP packet with the description and keywords
+    pub fn packet(&self) -> String {
+        let mut properties = String::new();
+        if let Some(description) = &self.description {
+            properties.push_str(&format!(
+                "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+                escape(description)
+            ));
+        }
+        if !self.subjects.is_empty() {
+            properties.push_str("   <dc:subject><rdf:Bag>");
+            for subject in &self.subjects {
+                properties.push_str(&format!("<rdf:li>{}</rdf:li>", escape(subject)));
+            }
+            properties.push_str("</rdf:Bag></dc:subject>\n");
+        }
+
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">
+{properties}  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>"
+        )
+    }
+}
+
+/// Sidecar of `image` the way digiKam and darktable name it, e.g. `a.jpg.xmp`
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".xmp");
+
+    PathBuf::from(path)
+}
+
+/// XMP of `image`, embedded or from a sidecar named `a.jpg.xmp` or `a.xmp`
+pub fn read(image: &Path) -> Result<Option<XmpCaption>, Error> {
+    let packet = match read_embedded(image)? {
+        Some(packet) => Some(packet),
+        None => [sidecar_path(image), image.with_extension("xmp")]
+            .iter()
+            .find(|path| path.is_file())
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .map_err(Error::io(format!("Failed to read XMP sidecar {path:#?}")))
+            })
+            .transpose()?,
+    };
+
+    Ok(packet.map(|packet| XmpCaption::parse(&packet)))
+}
+
+/// Whether XMP can be embedded in `image`, which must be a well-formed JPEG, PNG or WebP. The
+/// whole file is parsed, so images that only start like one get a sidecar instead.
+pub fn can_embed(image: &Path) -> bool {
+    std::fs::read(image).is_ok_and(|bytes| embed(&bytes, None).is_some())
+}
+
+/// XMP packet embedded in `image`, if it has one
+pub fn read_embedded(image: &Path) -> Result<Option<String>, Error> {
+    let bytes = std::fs::read(image).map_err(Error::io(format!("Failed to read {image:#?}")))?;
+
+    let packet = match Format::detect(&bytes) {
+        Some(Format::Jpeg) => jpeg_segments(&bytes).and_then(|(segments, _)| {
+            segments
+                .iter()
+                .find_map(|segment| {
+                    segment
+                        .strip_prefix(&[0xff, 0xe1])?
+                        .get(2..)?
+                        .strip_prefix(JPEG_NAMESPACE)
+                })
+                .map(<[u8]>::to_vec)
+        }),
+        Some(Format::Png) => png_chunks(&bytes).and_then(|chunks| {
+            chunks
+                .iter()
+                .find(|chunk| is_png_xmp(chunk))
+                .and_then(|chunk| {
+                    metadata::international_text(&chunk.data()[PNG_KEYWORD.len() + 1..])
+                })
+                .map(String::into_bytes)
+        }),
+        Some(Format::Webp) => riff_chunks(&bytes).and_then(|chunks| {
+            chunks
+                .iter()
+                .find(|(kind, _)| kind == b"XMP ")
+                .map(|(_, data)| data.to_vec())
+        }),
+        None => None,
+    };
+
+    Ok(packet.map(|packet| String::from_utf8_lossy(&packet).to_string()))
+}
+
+/// Replaces the XMP embedded in `image` with `packet`, or removes it if None
+pub fn write_embedded(image: &Path, packet: Option<&str>) -> Result<(), Error> {
+    let bytes = std::fs::read(image).map_err(Error::io(format!("Failed to read {image:#?}")))?;
+    let unsupported = || Error::Format {
+        context: format!("Failed to embed XMP into {image:#?}"),
+        reason: "The image isn't a well-formed JPEG, PNG or WebP".into(),
+    };
+
+    let written = embed(&bytes, packet).ok_or_else(unsupported)?;
+
+    fsops::write_atomic(image, written)
+}
+
+/// `image` with `packet` embedded in place of its XMP, None if it isn't well-formed
+fn embed(image: &[u8], packet: Option<&str>) -> Option<Vec<u8>> {
+    match Format::detect(image)? {
+        Format::Jpeg => embed_jpeg(image, packet),
+        Format::Png => embed_png(image, packet),
+        Format::Webp => embed_webp(image, packet),
+    }
+}
+
+enum Format {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl Format {
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xff, 0xd8]) {
+            Some(Format::Jpeg)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+            Some(Format::Webp)
+        } else {
+            None
+        }
+    }
+}
+
+/// The marker segments of a JPEG before its image data, and everything from there on
+fn jpeg_segments(jpeg: &[u8]) -> Option<(Vec<&[u8]>, &[u8])> {
+    let mut segments = Vec::new();
+    let mut position = 2;
+
+    loop {
+        let marker = *jpeg.get(position + 1)?;
+        if *jpeg.get(position)? != 0xff || marker == 0xda || marker == 0xd9 {
+            return Some((segments, &jpeg[position..]));
+        }
+
+        let length = u16::from_be_bytes([*jpeg.get(position + 2)?, *jpeg.get(position + 3)?]);
+        let end = position + 2 + length as usize;
+        segments.push(jpeg.get(position..end)?);
+        position = end;
+    }
+}
+
+fn embed_jpeg(jpeg: &[u8], packet: Option<&str>) -> Option<Vec<u8>> {
+    let (segments, rest) = jpeg_segments(jpeg)?;
+    let is_xmp = |segment: &&[u8]| {
+        segment.starts_with(&[0xff, 0xe1])
+            && segment
+                .get(4..)
+                .is_some_and(|payload| payload.starts_with(JPEG_NAMESPACE))
+    };
+    // JFIF and EXIF are expected to come first
+    let leads = |segment: &&[u8]| {
+        segment.starts_with(&[0xff, 0xe0])
+            || (segment.starts_with(&[0xff, 0xe1])
+                && segment
+                    .get(4..)
+                    .is_some_and(|payload| payload.starts_with(b"Exif\0")))
+    };
+
+    let segments: Vec<_> = segments
+        .into_iter()
+        .filter(|segment| !is_xmp(segment))
+        .collect();
+    let insert_at = segments
+        .iter()
+        .position(|segment| !leads(segment))
+        .unwrap_or(segments.len());
+
+    let mut written = vec![0xff, 0xd8];
+    for (index, segment) in segments.iter().enumerate() {
+        if index == insert_at
+            && let Some(packet) = packet
+        {
+            written.extend(jpeg_xmp_segment(packet)?);
+        }
+        written.extend(*segment);
+    }
+    if insert_at == segments.len()
+        && let Some(packet) = packet
+    {
+        written.extend(jpeg_xmp_segment(packet)?);
+    }
+    written.extend(rest);
+
+    Some(written)
+}
+
+/// APP1 segment holding `packet`. None if it is too large for a single segment
+fn jpeg_xmp_segment(packet: &str) -> Option<Vec<u8>> {
+    let length = u16::try_from(2 + JPEG_NAMESPACE.len() + packet.len()).ok()?;
+
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend(length.to_be_bytes());
+    segment.extend(JPEG_NAMESPACE);
+    segment.extend(packet.as_bytes());
+    Some(segment)
+}
+
+/// A chunk of a PNG, with its length, type and CRC
+struct PngChunk<'a>(&'a [u8]);
+
+impl PngChunk<'_> {
+    fn kind(&self) -> &[u8] {
+        &self.0[4..8]
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.0[8..self.0.len() - 4]
+    }
+}
+
+fn png_chunks(png: &[u8]) -> Option<Vec<PngChunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut position = 8;
+
+    while position < png.len() {
+        let length = u32::from_be_bytes(png.get(position..position + 4)?.try_into().ok()?);
+        let end = position + 12 + length as usize;
+        chunks.push(PngChunk(png.get(position..end)?));
+        position = end;
+    }
+
+    Some(chunks)
+}
+
+fn is_png_xmp(chunk: &PngChunk) -> bool {
+    chunk.kind() == b"iTXt"
+        && chunk.data().starts_with(PNG_KEYWORD)
+        && chunk.data().get(PNG_KEYWORD.len()) == Some(&0)
+}
+
+fn embed_png(png: &[u8], packet: Option<&str>) -> Option<Vec<u8>> {
+    let mut written = png[..8].to_vec();
+
+    for chunk in png_chunks(png)? {
+        if is_png_xmp(&chunk) {
+            continue;
+        }
+        written.extend(chunk.0);
+
+        // Right after the header, like other tools do
+        if chunk.kind() == b"IHDR"
+            && let Some(packet) = packet
+        {
+            // Uncompressed, without language tag or translated keyword
+            let mut data = PNG_KEYWORD.to_vec();
+            data.extend([0, 0, 0, 0, 0]);
+            data.extend(packet.as_bytes());
+
+            let mut crc = flate2::Crc::new();
+            crc.update(b"iTXt");
+            crc.update(&data);
+
+            written.extend(u32::try_from(data.len()).ok()?.to_be_bytes());
+            written.extend(b"iTXt");
+            written.extend(data);
+            written.extend(crc.sum().to_be_bytes());
+        }
+    }
+
+    Some(written)
+}
+
+/// (type, data) of the chunks of a WebP
+fn riff_chunks(webp: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut position = 12;
+
+    while position < webp.len() {
+        let kind = webp.get(position..position + 4)?.try_into().ok()?;
+        let length = u32::from_le_bytes(webp.get(position + 4..position + 8)?.try_into().ok()?);
+        let start = position + 8;
+        let end = start + length as usize;
+        chunks.push((kind, webp.get(start..end)?));
+        // Chunks are padded to an even length
+        position = end + (length as usize & 1);
+    }
+
+    Some(chunks)
+}
+
+fn embed_webp(webp: &[u8], packet: Option<&str>) -> Option<Vec<u8>> {
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = riff_chunks(webp)?
+        .into_iter()
+        .filter(|(kind, _)| kind != b"XMP ")
+        .map(|(kind, data)| (kind, data.to_vec()))
+        .collect();
+
+    // Only the extended format can hold metadata
+    if chunks.first()?.0 != *b"VP8X" {
+        let size = imagesize::blob_size(webp).ok()?;
+        let (kind, data) = chunks.first()?;
+        // The alpha bit follows the signature byte and the 14 bit width and height
+        let alpha = kind == b"VP8L"
+            && data
+                .get(1..5)
+                .is_some_and(|bits| u32::from_le_bytes(bits.try_into().unwrap()) & (1 << 28) != 0);
+
+        let mut extended = vec![if alpha { 0x10 } else { 0 }, 0, 0, 0];
+        // Stored minus one, so an image without pixels can't be described
+        let width = u32::try_from(size.width).ok()?.checked_sub(1)?;
+        let height = u32::try_from(size.height).ok()?.checked_sub(1)?;
+        extended.extend(&width.to_le_bytes()[..3]);
+        extended.extend(&height.to_le_bytes()[..3]);
+        chunks.insert(0, (*b"VP8X", extended));
+    }
+
+    let flags = chunks.first_mut()?.1.first_mut()?;
+    match packet {
+        Some(packet) => {
+            *flags |= WEBP_XMP_FLAG;
+            chunks.push((*b"XMP ", packet.as_bytes().to_vec()));
+        }
+        None => *flags &= !WEBP_XMP_FLAG,
+    }
+
+    let mut body = b"WEBP".to_vec();
+    for (kind, data) in chunks {
+        body.extend(kind);
+        body.extend(u32::try_from(data.len()).ok()?.to_le_bytes());
+        body.extend(&data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+
+    let mut written = b"RIFF".to_vec();
+    written.extend(u32::try_from(body.len()).ok()?.to_le_bytes());
+    written.extend(body);
+
+    Some(written)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    static ENTITY: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-z]+);").unwrap());
+
+    ENTITY
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .to_string()
+}
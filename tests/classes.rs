@@ -0,0 +1,120 @@
+use quicklabel::{CaptionOutput, CaptionOutputError, Class, ClassError};
+
+fn validated(label: &str, repeats: usize) -> Result<Class, ClassError> {
+    Class::validated(label, repeats, &[Class::new("dog", 10)])
+}
+
+#[test]
+fn path_separators_are_replaced() {
+    assert_eq!(Class::sanitize_label("cats/dogs"), "cats_dogs");
+    assert_eq!(Class::sanitize_label("cats\\dogs"), "cats_dogs");
+    assert_eq!(Class::sanitize_label("../escape"), ".._escape");
+    assert_eq!(Class::sanitize_label("/"), "_");
+}
+
+#[test]
+fn characters_illegal_on_windows_are_replaced() {
+    assert_eq!(Class::sanitize_label("a<b>c:d\"e|f?g*h"), "a_b_c_d_e_f_g_h");
+    assert_eq!(Class::sanitize_label("tab\there\u{0}"), "tab_here_");
+}
+
+#[test]
+fn surrounding_whitespace_and_trailing_dots_are_dropped() {
+    assert_eq!(Class::sanitize_label("  cat  "), "cat");
+    assert_eq!(Class::sanitize_label("cat. . "), "cat");
+    assert_eq!(Class::sanitize_label(".hidden"), ".hidden");
+    assert_eq!(Class::sanitize_label("black cat"), "black cat");
+    assert_eq!(Class::sanitize_label("猫"), "猫");
+}
+
+#[test]
+fn labels_that_sanitize_to_nothing_are_rejected() {
+    assert_eq!(validated("", 1), Err(ClassError::EmptyLabel));
+    assert_eq!(validated("   ", 1), Err(ClassError::EmptyLabel));
+    assert_eq!(validated("..", 1), Err(ClassError::EmptyLabel));
+    assert_eq!(validated(".", 1), Err(ClassError::EmptyLabel));
+}
+
+#[test]
+fn repeats_and_length_are_checked() {
+    assert_eq!(validated("cat", 0), Err(ClassError::ZeroRepeats));
+    assert_eq!(validated(&"a".repeat(300), 1), Err(ClassError::TooLong));
+    assert!(validated(&"a".repeat(253), 1).is_ok());
+    assert_eq!(validated(&"a".repeat(254), 1), Err(ClassError::TooLong));
+}
+
+#[test]
+fn duplicates_are_detected() {
+    let dog = Class::new("dog", 10);
+
+    assert_eq!(
+        validated("dog", 10),
+        Err(ClassError::Duplicate(dog.clone()))
+    );
+    assert_eq!(validated("dog", 3), Err(ClassError::Duplicate(dog.clone())));
+    assert_eq!(validated("Dog ", 3), Err(ClassError::Duplicate(dog)));
+    assert!(Class::validated("dog", 3, []).is_ok());
+}
+
+#[test]
+fn valid_classes_are_sanitized() {
+    let class = validated(" cats/dogs. ", 3).unwrap();
+
+    assert_eq!(class, Class::new("cats_dogs", 3));
+    assert_eq!(class.to_string(), "3_cats_dogs");
+}
+
+#[test]
+fn triggers_are_inserted_into_captions() {
+    let dog = Class::new("dog", 10).with_trigger(" ohwx dog ");
+
+    assert_eq!(dog.trigger.as_deref(), Some("ohwx dog"));
+    assert_eq!(dog.insert_trigger("on a beach"), "ohwx dog, on a beach");
+    assert_eq!(
+        dog.insert_trigger("a photo of {trigger}"),
+        "a photo of ohwx dog"
+    );
+    assert_eq!(dog.insert_trigger("ohwx dog running"), "ohwx dog running");
+    assert_eq!(dog.insert_trigger(""), "ohwx dog");
+    assert_eq!(
+        dog.fill_placeholders("{class} on a beach"),
+        "dog on a beach"
+    );
+
+    assert!(!dog.mentions_trigger("a dog"));
+    assert!(Class::new("cat", 3).mentions_trigger("a dog"));
+    assert_eq!(Class::new("cat", 3).with_trigger("  ").trigger, None);
+    assert_eq!(
+        Class::new("cat", 3).insert_trigger("{trigger}"),
+        "{trigger}"
+    );
+}
+
+#[test]
+fn caption_outputs_are_checked() {
+    let existing = [CaptionOutput::new("txt", "Prompt")];
+    let validated =
+        |extension: &str, field: &str| CaptionOutput::validated(extension, field, &existing);
+
+    assert_eq!(
+        validated(" .caption ", " Description "),
+        Ok(CaptionOutput {
+            extension: "caption".into(),
+            field: "Description".into()
+        })
+    );
+    assert_eq!(
+        validated("", "Tags"),
+        Err(CaptionOutputError::EmptyExtension)
+    );
+    assert_eq!(
+        validated("tar.gz", "Tags"),
+        Err(CaptionOutputError::InvalidExtension)
+    );
+    assert_eq!(
+        validated("PNG", "Tags"),
+        Err(CaptionOutputError::ReservedExtension)
+    );
+    assert_eq!(validated("tags", " "), Err(CaptionOutputError::EmptyField));
+    assert_eq!(validated("TXT", "Tags"), Err(CaptionOutputError::Duplicate));
+}
@@ -0,0 +1,43 @@
+use std::process::Command;
+
+/// The binary, without any of the variables filling in the GUI from the environment it runs in
+fn quicklabel() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_quicklabel"));
+    for variable in ["INPUT", "OUTPUT", "TRASH", "CLASSES", "PREFILL", "PROJECT"] {
+        command.env_remove(format!("QUICKLABEL_{variable}"));
+    }
+    command
+}
+
+#[test]
+fn launch_variables_are_ignored_by_commands() {
+    let root = tempfile::tempdir().unwrap();
+
+    let output = quicklabel()
+        .args(["stats", "--output"])
+        .arg(root.path())
+        .env("QUICKLABEL_PREFILL", "a photo of")
+        .env("QUICKLABEL_CLASSES", "10:dog")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Total"));
+}
+
+#[test]
+fn launch_arguments_conflict_with_commands() {
+    let root = tempfile::tempdir().unwrap();
+
+    let output = quicklabel()
+        .args(["--prefill", "a photo of", "stats", "--output"])
+        .arg(root.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,699 @@
+use std::{fs, path::Path};
+
+use quicklabel::{
+    CaptionOutput, Class, Dataset, Error, ImageStatus, LabelQueue, Progress,
+    collision::CollisionPolicy,
+    fsops::{self, TransferMode},
+    journal::{INTENT_LOG_NAME, JournalEntry},
+};
+use tempfile::TempDir;
+
+/// Temporary input and output directories, with the input holding `images`
+fn setup(images: &[&str]) -> (TempDir, Dataset) {
+    let root = tempfile::tempdir().unwrap();
+    let input = root.path().join("input");
+    let output = root.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::create_dir_all(&output).unwrap();
+
+    for image in images {
+        fs::write(input.join(image), image.as_bytes()).unwrap();
+    }
+
+    let mut dataset = Dataset::new(input, output);
+    dataset.classes.push(Class::new("dog", 10));
+    dataset.classes.push(Class::new("cat", 3));
+    dataset.prepare().unwrap();
+
+    (root, dataset)
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+/// Writes the intent log the way an operation on `entry` leaves it when it is interrupted
+fn interrupt(dataset: &Dataset, entry: &JournalEntry, revert: bool) {
+    let mut intent = toml::Table::new();
+    intent.insert("entry".into(), toml::Value::try_from(entry).unwrap());
+    intent.insert("revert".into(), revert.into());
+    fs::write(
+        dataset.output_dir.join(INTENT_LOG_NAME),
+        toml::to_string(&intent).unwrap(),
+    )
+    .unwrap();
+}
+
+/// Labeling of `a.png` with a sidecar next to it, planned but not carried out
+fn planned_label(dataset: &Dataset) -> JournalEntry {
+    let image = dataset.input_dir.join("a.png");
+    fs::write(image.with_extension("caption"), "a blip caption").unwrap();
+
+    dataset
+        .plan_label(&image, &dataset.classes[0], "dog")
+        .resolve(dataset.collision_policy)
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn index_picks_up_images_sorted() {
+    let (_root, dataset) = setup(&["b.png", "a.JPG", "c.webp", "notes.txt", "d.gif"]);
+    fs::create_dir(dataset.input_dir.join("folder.png")).unwrap();
+
+    let names: Vec<_> = dataset
+        .index()
+        .unwrap()
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+
+    assert_eq!(names, ["a.JPG", "b.png", "c.webp"]);
+}
+
+#[test]
+fn prepare_creates_class_directories() {
+    let (_root, dataset) = setup(&[]);
+
+    assert!(dataset.output_dir.join("10_dog").is_dir());
+    assert!(dataset.output_dir.join("3_cat").is_dir());
+    assert_eq!(
+        dataset.classes[1].path(&dataset.output_dir),
+        dataset.output_dir.join("3_cat")
+    );
+}
+
+#[test]
+fn label_moves_image_and_writes_caption() {
+    let (_root, dataset) = setup(&["a.png"]);
+    let image = dataset.input_dir.join("a.png");
+
+    let entry = dataset
+        .label(&image, &dataset.classes[0], "a photo of a dog")
+        .unwrap()
+        .unwrap();
+
+    let class_dir = dataset.output_dir.join("10_dog");
+    assert!(!image.exists());
+    assert_eq!(entry.destination, Some(class_dir.join("a.png")));
+    assert_eq!(read(&class_dir.join("a.png")), "a.png");
+    assert_eq!(read(&class_dir.join("a.txt")), "a photo of a dog");
+    assert!(!dataset.output_dir.join(INTENT_LOG_NAME).exists());
+}
+
+#[test]
+fn undo_restores_input() {
+    let (_root, dataset) = setup(&["a.png"]);
+    let image = dataset.input_dir.join("a.png");
+
+    let entry = dataset
+        .label(&image, &dataset.classes[1], "cat")
+        .unwrap()
+        .unwrap();
+    dataset.undo(&entry).unwrap();
+
+    let class_dir = dataset.output_dir.join("3_cat");
+    assert_eq!(read(&image), "a.png");
+    assert!(!class_dir.join("a.png").exists());
+    assert!(!class_dir.join("a.txt").exists());
+}
+
+#[test]
+fn recovery_rolls_back_moves_that_didnt_start() {
+    let (_root, dataset) = setup(&["a.png"]);
+    let entry = planned_label(&dataset);
+    interrupt(&dataset, &entry, false);
+
+    dataset.prepare().unwrap();
+
+    let destination = entry.destination.unwrap();
+    assert_eq!(read(&entry.source), "a.png");
+    assert_eq!(
+        read(&entry.source.with_extension("caption")),
+        "a blip caption"
+    );
+    assert!(!destination.exists());
+    assert!(!destination.with_extension("txt").exists());
+    assert!(!dataset.output_dir.join(INTENT_LOG_NAME).exists());
+}
+
+#[test]
+fn recovery_finishes_moves_after_the_transfer() {
+    for step in ["transferred", "captioned", "sidecars removed"] {
+        let (_root, dataset) = setup(&["a.png"]);
+        let entry = planned_label(&dataset);
+        let destination = entry.destination.clone().unwrap();
+        interrupt(&dataset, &entry, false);
+
+        fs::rename(&entry.source, &destination).unwrap();
+        if step != "transferred" {
+            fs::write(destination.with_extension("txt"), "dog").unwrap();
+        }
+        if step == "sidecars removed" {
+            fs::remove_file(entry.source.with_extension("caption")).unwrap();
+        }
+
+        dataset.prepare().unwrap();
+
+        assert!(!entry.source.exists(), "{step}");
+        assert!(!entry.source.with_extension("caption").exists(), "{step}");
+        assert_eq!(read(&destination), "a.png", "{step}");
+        assert_eq!(read(&destination.with_extension("txt")), "dog", "{step}");
+        assert!(!dataset.output_dir.join(INTENT_LOG_NAME).exists(), "{step}");
+    }
+}
+
+#[test]
+fn recovery_finishes_interrupted_undos() {
+    let (_root, dataset) = setup(&["a.png"]);
+    let entry = planned_label(&dataset);
+    let destination = entry.destination.clone().unwrap();
+    entry.apply(&dataset.output_dir).unwrap();
+
+    // The image is back, but its caption and sidecar aren't dealt with yet
+    fs::rename(&destination, &entry.source).unwrap();
+    interrupt(&dataset, &entry, true);
+
+    dataset.prepare().unwrap();
+
+    assert_eq!(read(&entry.source), "a.png");
+    assert_eq!(
+        read(&entry.source.with_extension("caption")),
+        "a blip caption"
+    );
+    assert!(!destination.exists());
+    assert!(!destination.with_extension("txt").exists());
+    assert!(!dataset.output_dir.join(INTENT_LOG_NAME).exists());
+}
+
+#[test]
+fn recovery_stops_when_the_image_is_gone() {
+    let (_root, dataset) = setup(&["a.png"]);
+    let entry = planned_label(&dataset);
+    interrupt(&dataset, &entry, false);
+    fs::remove_file(&entry.source).unwrap();
+
+    let error = dataset.prepare().unwrap_err();
+
+    assert!(matches!(error, Error::Unrecoverable(..)));
+    assert!(!error.is_recoverable());
+    // Kept so the user can find out what happened
+    assert!(dataset.output_dir.join(INTENT_LOG_NAME).exists());
+    assert!(dataset.prepare().is_err());
+}
+
+#[test]
+fn failed_labels_are_settled_before_trying_again() {
+    let (_root, dataset) = setup(&["a.png", "b.png"]);
+    let entry = planned_label(&dataset);
+    let destination = entry.destination.clone().unwrap();
+    let log = dataset.output_dir.join(INTENT_LOG_NAME);
+
+    // The caption can't be written once the image is moved
+    fs::create_dir(destination.with_extension("txt")).unwrap();
+    assert!(entry.apply(&dataset.output_dir).is_err());
+    assert!(!entry.source.exists());
+    assert!(log.exists());
+
+    // Labeling another image first mustn't lose track of it
+    let b = dataset.input_dir.join("b.png");
+    assert!(dataset.label(&b, &dataset.classes[0], "dog").is_err());
+    assert!(b.exists());
+    assert!(log.exists());
+
+    fs::remove_dir(destination.with_extension("txt")).unwrap();
+    assert!(entry.settle(&dataset.output_dir, false).unwrap());
+    assert_eq!(read(&destination), "a.png");
+    assert_eq!(read(&destination.with_extension("txt")), "dog");
+    assert!(!entry.source.with_extension("caption").exists());
+    assert!(!log.exists());
+
+    // Nothing was moved, so it is rolled back and has to be done again
+    let entry = dataset
+        .plan_label(&b, &dataset.classes[1], "cat")
+        .resolve(dataset.collision_policy)
+        .unwrap()
+        .unwrap();
+    let cat_dir = dataset.output_dir.join("3_cat");
+    fs::remove_dir(&cat_dir).unwrap();
+    assert!(entry.apply(&dataset.output_dir).is_err());
+    assert!(log.exists());
+
+    assert!(!entry.settle(&dataset.output_dir, false).unwrap());
+    assert_eq!(read(&b), "b.png");
+    assert!(!log.exists());
+    fs::create_dir(&cat_dir).unwrap();
+    entry.apply(&dataset.output_dir).unwrap();
+    assert_eq!(read(&cat_dir.join("b.txt")), "cat");
+}
+
+#[test]
+fn trash_without_trash_dir_leaves_image() {
+    let (_root, mut dataset) = setup(&["a.png", "b.png"]);
+    let a = dataset.input_dir.join("a.png");
+    let b = dataset.input_dir.join("b.png");
+
+    let entry = dataset.trash(&a).unwrap().unwrap();
+    assert!(a.exists());
+    assert!(!entry.removes_source());
+
+    let trash = dataset.output_dir.join("trash");
+    fs::create_dir(&trash).unwrap();
+    dataset.trash_dir = Some(trash.clone());
+
+    dataset.trash(&b).unwrap().unwrap();
+    assert!(!b.exists());
+    assert!(trash.join("b.png").exists());
+    assert!(!trash.join("b.txt").exists());
+}
+
+#[test]
+fn copy_mode_leaves_input_untouched() {
+    let (_root, mut dataset) = setup(&["a.png"]);
+    dataset.transfer_mode = TransferMode::Copy;
+    let image = dataset.input_dir.join("a.png");
+
+    let entry = dataset
+        .label(&image, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+
+    assert!(image.exists());
+    assert!(dataset.output_dir.join("10_dog").join("a.png").exists());
+    assert!(!entry.removes_source());
+}
+
+#[cfg(unix)]
+#[test]
+fn link_modes_leave_input_untouched() {
+    use std::os::unix::fs::MetadataExt;
+
+    for mode in [TransferMode::Hardlink, TransferMode::Symlink] {
+        let (_root, mut dataset) = setup(&["a.png"]);
+        dataset.transfer_mode = mode;
+        let image = dataset.input_dir.join("a.png");
+
+        let entry = dataset
+            .label(&image, &dataset.classes[0], "dog")
+            .unwrap()
+            .unwrap();
+        let destination = entry.destination.clone().unwrap();
+
+        assert!(!entry.removes_source(), "{mode}");
+        assert_eq!(read(&image), "a.png", "{mode}");
+        assert_eq!(read(&destination), "a.png", "{mode}");
+        assert_eq!(read(&destination.with_extension("txt")), "dog", "{mode}");
+        match mode {
+            TransferMode::Hardlink => assert_eq!(
+                fs::metadata(&destination).unwrap().ino(),
+                fs::metadata(&image).unwrap().ino()
+            ),
+            _ => assert_eq!(fs::read_link(&destination).unwrap(), image),
+        }
+
+        // Only the link goes, the image it points at stays
+        dataset.undo(&entry).unwrap();
+        assert_eq!(read(&image), "a.png", "{mode}");
+        assert!(!fsops::exists(&destination), "{mode}");
+        assert!(!destination.with_extension("txt").exists(), "{mode}");
+    }
+}
+
+/// Hardlinks can't reach other file systems, and neither can renames
+#[cfg(unix)]
+#[test]
+fn transfers_across_file_systems_fall_back_to_copies() {
+    use std::os::unix::fs::MetadataExt;
+
+    let (root, mut dataset) = setup(&[]);
+    // Only there on some systems, and not always a file system of its own
+    let Ok(other) = tempfile::tempdir_in("/dev/shm") else {
+        return;
+    };
+    let device = |path: &Path| fs::metadata(path).unwrap().dev();
+    if device(other.path()) == device(root.path()) {
+        return;
+    }
+    dataset.input_dir = other.path().to_path_buf();
+    let a = dataset.input_dir.join("a.png");
+    let b = dataset.input_dir.join("b.png");
+    fs::write(&a, "a.png").unwrap();
+    fs::write(&b, "b.png").unwrap();
+
+    dataset.transfer_mode = TransferMode::Hardlink;
+    let entry = dataset
+        .label(&a, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+    let destination = entry.destination.clone().unwrap();
+    assert_eq!(read(&a), "a.png");
+    assert_eq!(read(&destination), "a.png");
+    dataset.undo(&entry).unwrap();
+    assert_eq!(read(&a), "a.png");
+    assert!(!destination.exists());
+
+    dataset.transfer_mode = TransferMode::Move;
+    let entry = dataset
+        .label(&b, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+    let destination = entry.destination.clone().unwrap();
+    assert!(!b.exists());
+    assert_eq!(read(&destination), "b.png");
+    dataset.undo(&entry).unwrap();
+    assert_eq!(read(&b), "b.png");
+    assert!(!destination.exists());
+}
+
+#[test]
+fn collisions_follow_policy() {
+    let (_root, mut dataset) = setup(&["a.png"]);
+    let class_dir = dataset.output_dir.join("10_dog");
+    fs::write(class_dir.join("a.png"), "existing").unwrap();
+    let image = dataset.input_dir.join("a.png");
+
+    dataset.collision_policy = CollisionPolicy::Ask;
+    assert!(
+        dataset
+            .label(&image, &dataset.classes[0], "dog")
+            .unwrap()
+            .is_none()
+    );
+    assert!(image.exists());
+
+    dataset.collision_policy = CollisionPolicy::AutoSuffix;
+    let entry = dataset
+        .label(&image, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.destination, Some(class_dir.join("a_1.png")));
+    assert_eq!(read(&class_dir.join("a.png")), "existing");
+    assert_eq!(read(&class_dir.join("a_1.txt")), "dog");
+}
+
+#[test]
+fn content_hash_names_follow_the_content() {
+    let (_root, mut dataset) = setup(&["a.png", "b.png"]);
+    dataset.collision_policy = CollisionPolicy::ContentHash;
+    let class_dir = dataset.output_dir.join("10_dog");
+    let copy = dataset.input_dir.join("copy.png");
+    fs::write(&copy, "a.png").unwrap();
+
+    let label = |name: &str| {
+        dataset
+            .label(&dataset.input_dir.join(name), &dataset.classes[0], name)
+            .unwrap()
+            .unwrap()
+            .destination
+            .unwrap()
+    };
+    let a = label("a.png");
+    let hash = a.file_stem().unwrap().to_string_lossy().to_string();
+    assert_eq!(hash.len(), 16);
+    assert_eq!(a, class_dir.join(format!("{hash}.png")));
+
+    // Different content gets its own name
+    let b = label("b.png");
+    assert_ne!(b.file_stem(), a.file_stem());
+    assert_eq!(read(&b), "b.png");
+
+    // The same content can't take the name of the file it duplicates
+    let copy = label("copy.png");
+    assert_eq!(copy, class_dir.join(format!("{hash}_1.png")));
+    assert_eq!(read(&a), "a.png");
+    assert_eq!(read(&a.with_extension("txt")), "a.png");
+    assert_eq!(read(&copy.with_extension("txt")), "copy.png");
+}
+
+#[test]
+fn sequential_names_continue_after_existing_files() {
+    let (_root, mut dataset) = setup(&["a.png", "b.jpg"]);
+    dataset.collision_policy = CollisionPolicy::Sequential;
+    let class_dir = dataset.output_dir.join("10_dog");
+    fs::write(class_dir.join("dog_0003.png"), "existing").unwrap();
+    fs::write(class_dir.join("dog_0001.txt"), "existing").unwrap();
+    fs::write(class_dir.join("other_0009.png"), "existing").unwrap();
+
+    for (name, expected) in [("a.png", "dog_0004.png"), ("b.jpg", "dog_0005.jpg")] {
+        let entry = dataset
+            .label(&dataset.input_dir.join(name), &dataset.classes[0], "dog")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.destination, Some(class_dir.join(expected)));
+    }
+    assert_eq!(read(&class_dir.join("dog_0003.png")), "existing");
+    assert_eq!(read(&class_dir.join("dog_0004.png")), "a.png");
+    assert_eq!(read(&class_dir.join("dog_0005.txt")), "dog");
+}
+
+#[test]
+fn queue_defers_skipped_images_to_the_end() {
+    let (_root, dataset) = setup(&["a.png", "b.png", "c.png"]);
+    let mut queue = LabelQueue::new(dataset.index().unwrap(), Progress::default());
+    assert_eq!(queue.cursor(), 0);
+
+    queue.select(0);
+    queue.defer_current();
+
+    let next = queue.next_queued().unwrap();
+    let image = queue.select(next).to_path_buf();
+    assert!(image.ends_with("b.png"));
+    let entry = dataset
+        .label(&image, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+    queue.record_applied(&entry);
+
+    let next = queue.next_queued().unwrap();
+    assert!(queue.select(next).ends_with("c.png"));
+    queue.defer_current();
+
+    // Only deferred images are left, starting with the first after the cursor
+    let next = queue.next_queued().unwrap();
+    assert!(queue.select(next).ends_with("a.png"));
+    assert!(queue.is_revisiting());
+    assert_eq!(queue.count(ImageStatus::Deferred), 2);
+    assert_eq!(queue.open(), 2);
+    assert_eq!(queue.labeled(), 1);
+}
+
+#[test]
+fn queue_resumes_from_progress() {
+    let (_root, dataset) = setup(&["a.png", "b.png", "c.png", "d.png"]);
+    let images = dataset.index().unwrap();
+
+    let mut queue = LabelQueue::new(images.clone(), Progress::default());
+    queue.select(0);
+    queue.defer_current();
+    queue.select(2);
+    let progress = queue.progress();
+
+    // The image under the cursor was dealt with meanwhile, so continue with the one after it
+    let remaining = images
+        .iter()
+        .filter(|path| !path.ends_with("c.png"))
+        .cloned()
+        .collect();
+    let queue = LabelQueue::new(remaining, progress);
+
+    assert!(queue.images()[queue.cursor()].ends_with("d.png"));
+    assert_eq!(queue.status(0), ImageStatus::Deferred);
+    assert_eq!(queue.find("b"), Some(1));
+    assert_eq!(queue.find("1"), Some(0));
+}
+
+#[test]
+fn editing_class_renames_directory() {
+    let (_root, mut dataset) = setup(&["a.png"]);
+    let image = dataset.input_dir.join("a.png");
+    dataset
+        .label(&image, &dataset.classes[0], "dog")
+        .unwrap()
+        .unwrap();
+
+    dataset.edit_class(0, Class::new("puppy", 5)).unwrap();
+
+    let renamed = dataset.output_dir.join("5_puppy");
+    assert_eq!(dataset.classes[0], Class::new("puppy", 5));
+    assert!(!dataset.output_dir.join("10_dog").exists());
+    assert_eq!(read(&renamed.join("a.txt")), "dog");
+
+    // Editing into another class would leave two identical classes
+    assert!(dataset.edit_class(0, Class::new("cat", 3)).is_err());
+    assert!(renamed.join("a.png").exists());
+}
+
+#[test]
+fn merging_classes_keeps_captions_with_images() {
+    let (_root, mut dataset) = setup(&["a.png", "b.png"]);
+    let cat_dir = dataset.output_dir.join("3_cat");
+    fs::write(cat_dir.join("a.png"), "cat").unwrap();
+    fs::write(cat_dir.join("a.txt"), "a cat").unwrap();
+    fs::write(cat_dir.join("a.npz"), "latents").unwrap();
+
+    for (image, caption) in [("a.png", "dog a"), ("b.png", "dog b")] {
+        let image = dataset.input_dir.join(image);
+        dataset
+            .label(&image, &dataset.classes[0], caption)
+            .unwrap()
+            .unwrap();
+    }
+
+    dataset.merge_classes(0, 1).unwrap();
+
+    assert_eq!(dataset.classes, [Class::new("cat", 3)]);
+    assert!(!dataset.output_dir.join("10_dog").exists());
+    assert_eq!(read(&cat_dir.join("a.png")), "cat");
+    assert_eq!(read(&cat_dir.join("a.txt")), "a cat");
+    assert_eq!(read(&cat_dir.join("a_1.png")), "a.png");
+    assert_eq!(read(&cat_dir.join("a_1.txt")), "dog a");
+    assert_eq!(read(&cat_dir.join("b.txt")), "dog b");
+    assert_eq!(read(&cat_dir.join("a.npz")), "latents");
+}
+
+#[test]
+fn sidecars_prefill_and_go_along_with_images() {
+    let (_root, mut dataset) = setup(&["a.png", "b.png"]);
+    let a = dataset.input_dir.join("a.png");
+    let b = dataset.input_dir.join("b.png");
+    fs::write(a.with_extension("caption"), "a blip caption\n").unwrap();
+    fs::write(a.with_extension("txt"), " ").unwrap();
+    fs::write(b.with_extension("txt"), "wd14, tags").unwrap();
+    fs::write(b.with_extension("caption"), b"latin-1 caf\xe9").unwrap();
+
+    assert_eq!(
+        dataset.sidecar_caption(&a).as_deref(),
+        Some("a blip caption")
+    );
+
+    // Labeling replaces the sidecars with the new caption, undoing brings them back
+    let entry = dataset
+        .label(&a, &dataset.classes[0], "a dog")
+        .unwrap()
+        .unwrap();
+    assert!(!a.with_extension("caption").exists());
+    assert!(!a.with_extension("txt").exists());
+    assert!(!dataset.output_dir.join("10_dog").join("a.caption").exists());
+
+    dataset.undo(&entry).unwrap();
+    assert_eq!(read(&a.with_extension("caption")), "a blip caption\n");
+    assert_eq!(read(&a.with_extension("txt")), " ");
+
+    // Trashed images keep their sidecars
+    let trash = dataset.output_dir.join("trash");
+    fs::create_dir(&trash).unwrap();
+    dataset.trash_dir = Some(trash.clone());
+    dataset.trash(&b).unwrap().unwrap();
+    assert!(!b.with_extension("txt").exists());
+    assert_eq!(read(&trash.join("b.txt")), "wd14, tags");
+    assert_eq!(
+        fs::read(trash.join("b.caption")).unwrap(),
+        b"latin-1 caf\xe9"
+    );
+
+    dataset.sidecar_extensions.clear();
+    assert_eq!(dataset.sidecar_caption(&a), None);
+}
+
+#[test]
+fn interrupted_merges_are_finished() {
+    let (_root, dataset) = setup(&[]);
+    let dog_dir = dataset.output_dir.join("10_dog");
+    let cat_dir = dataset.output_dir.join("3_cat");
+    for (file, contents) in [("a.png", "dog"), ("a.txt", "a dog"), ("b.png", "dog")] {
+        fs::write(dog_dir.join(file), contents).unwrap();
+    }
+
+    // Crashed once the first image was moved, but before its caption was
+    let moves = [
+        ("a.png", "a_1.png"),
+        ("a.txt", "a_1.txt"),
+        ("b.png", "b.png"),
+    ]
+    .map(|(from, to)| vec![dog_dir.join(from), cat_dir.join(to)]);
+    let mut merge = toml::Table::new();
+    merge.insert("from".into(), toml::Value::try_from(&dog_dir).unwrap());
+    merge.insert("moves".into(), toml::Value::try_from(moves).unwrap());
+    fs::write(
+        dataset.output_dir.join(INTENT_LOG_NAME),
+        toml::to_string(&merge).unwrap(),
+    )
+    .unwrap();
+    fs::rename(dog_dir.join("a.png"), cat_dir.join("a_1.png")).unwrap();
+
+    dataset.prepare().unwrap();
+
+    assert_eq!(read(&cat_dir.join("a_1.png")), "dog");
+    assert_eq!(read(&cat_dir.join("a_1.txt")), "a dog");
+    assert_eq!(read(&cat_dir.join("b.png")), "dog");
+    // The class is still there until the merge is saved, just empty
+    assert_eq!(fs::read_dir(&dog_dir).unwrap().count(), 0);
+    assert!(!dataset.output_dir.join(INTENT_LOG_NAME).exists());
+}
+
+#[test]
+fn merges_keep_names_apart() {
+    let (_root, mut dataset) = setup(&[]);
+    let dog_dir = dataset.output_dir.join("10_dog");
+    let cat_dir = dataset.output_dir.join("3_cat");
+    for file in ["a.png", "a_1.png"] {
+        fs::write(dog_dir.join(file), file).unwrap();
+        fs::write(dog_dir.join(file).with_extension("txt"), file).unwrap();
+    }
+    fs::write(cat_dir.join("a.png"), "cat").unwrap();
+
+    dataset.merge_classes(0, 1).unwrap();
+
+    assert_eq!(read(&cat_dir.join("a.png")), "cat");
+    assert_eq!(read(&cat_dir.join("a_1.png")), "a.png");
+    assert_eq!(read(&cat_dir.join("a_1.txt")), "a.png");
+    assert_eq!(read(&cat_dir.join("a_1_1.png")), "a_1.png");
+    assert_eq!(read(&cat_dir.join("a_1_1.txt")), "a_1.png");
+    assert!(!dog_dir.exists());
+}
+
+#[test]
+fn caption_outputs_get_the_text_of_their_field() {
+    let (_root, mut dataset) = setup(&["a.png", "b.png"]);
+    dataset.caption_outputs = vec![
+        CaptionOutput::new("txt", "Tags"),
+        CaptionOutput::new("caption", "Description"),
+        CaptionOutput::new("tags", "Tags"),
+    ];
+    assert_eq!(dataset.caption_fields(), ["Tags", "Description"]);
+
+    let a = dataset.input_dir.join("a.png");
+    let class_dir = dataset.output_dir.join("10_dog");
+    let entry = dataset
+        .plan_label_fields(
+            &a,
+            &dataset.classes[0],
+            &["dog, beach".into(), "A dog on a beach".into()],
+        )
+        .resolve(dataset.collision_policy)
+        .unwrap()
+        .unwrap();
+    entry.apply(&dataset.output_dir).unwrap();
+
+    assert_eq!(read(&class_dir.join("a.txt")), "dog, beach");
+    assert_eq!(read(&class_dir.join("a.caption")), "A dog on a beach");
+    assert_eq!(read(&class_dir.join("a.tags")), "dog, beach");
+
+    dataset.undo(&entry).unwrap();
+    assert!(!class_dir.join("a.txt").exists());
+    assert!(!class_dir.join("a.caption").exists());
+    assert!(!class_dir.join("a.tags").exists());
+
+    // Every caption file has to be free for the name to be used
+    fs::write(class_dir.join("b.caption"), "taken").unwrap();
+    dataset
+        .label(
+            &dataset.input_dir.join("b.png"),
+            &dataset.classes[0],
+            "a dog",
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(read(&class_dir.join("b_1.txt")), "a dog");
+    assert_eq!(read(&class_dir.join("b_1.caption")), "a dog");
+}
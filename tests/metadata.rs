@@ -0,0 +1,124 @@
+use std::fs;
+
+use quicklabel::{Generation, metadata::strip_prompt_syntax};
+
+const PARAMETERS: &str = "masterpiece, (a dog:1.2), <lora:dogs:0.8>
+on a beach
+Negative prompt: blurry, lowres
+Steps: 20, Sampler: Euler a, CFG scale: 7, Seed: 1234, Size: 512x512, Model hash: abc123, Model: sd_xl_base_1.0, Lora hashes: \"dogs: 1a2b, cats: 3c4d\"";
+
+/// A PNG with the given chunks before its (empty) image data
+fn png(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in chunks.iter().chain([&(b"IDAT", Vec::new())]) {
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(*kind);
+        png.extend(data);
+        // The CRC isn't checked
+        png.extend([0; 4]);
+    }
+    png
+}
+
+#[test]
+fn a1111_parameters_are_parsed() {
+    let generation = Generation::from_parameters(PARAMETERS).unwrap();
+
+    assert_eq!(
+        generation.prompt,
+        "masterpiece, (a dog:1.2), <lora:dogs:0.8>\non a beach"
+    );
+    assert_eq!(
+        generation.negative_prompt.as_deref(),
+        Some("blurry, lowres")
+    );
+    assert_eq!(generation.seed.as_deref(), Some("1234"));
+    assert_eq!(generation.model.as_deref(), Some("sd_xl_base_1.0"));
+    assert_eq!(generation.settings[0], ("Steps".into(), "20".into()));
+    assert_eq!(
+        generation.settings.last().unwrap(),
+        &("Lora hashes".into(), "dogs: 1a2b, cats: 3c4d".into())
+    );
+}
+
+#[test]
+fn comfy_graphs_are_followed_to_the_prompts() {
+    let prompt = r#"{
+        "3": {"class_type": "KSampler", "inputs": {"seed": 42, "steps": 30, "sampler_name": "euler", "positive": ["6", 0], "negative": ["7", 0], "model": ["4", 0]}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "dreamshaper.safetensors"}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": ["8", 0]}},
+        "7": {"class_type": "CLIPTextEncode", "inputs": {"text": "ugly"}},
+        "8": {"class_type": "PrimitiveString", "inputs": {"value": "a cat"}}
+    }"#;
+    let generation = Generation::from_comfy_prompt(prompt).unwrap();
+
+    assert_eq!(generation.prompt, "a cat");
+    assert_eq!(generation.negative_prompt.as_deref(), Some("ugly"));
+    assert_eq!(generation.seed.as_deref(), Some("42"));
+    assert_eq!(generation.model.as_deref(), Some("dreamshaper.safetensors"));
+    assert_eq!(
+        generation.settings,
+        [
+            ("steps".into(), "30".into()),
+            ("sampler_name".into(), "euler".into())
+        ]
+    );
+
+    let workflow = r#"{
+        "nodes": [
+            {"id": 1, "type": "KSampler", "inputs": [{"name": "positive", "link": 10}, {"name": "negative", "link": 11}]},
+            {"id": 2, "type": "CLIPTextEncode", "widgets_values": ["a bird"]},
+            {"id": 3, "type": "CLIPTextEncode", "widgets_values": ["noisy"]}
+        ],
+        "links": [[10, 2, 0, 1, 1, "CONDITIONING"], [11, 3, 0, 1, 2, "CONDITIONING"]]
+    }"#;
+    let generation = Generation::from_comfy_workflow(workflow).unwrap();
+
+    assert_eq!(generation.prompt, "a bird");
+    assert_eq!(generation.negative_prompt.as_deref(), Some("noisy"));
+}
+
+#[test]
+fn png_text_chunks_are_read() {
+    let root = tempfile::tempdir().unwrap();
+
+    let generated = root.path().join("a1111.png");
+    let mut text = b"parameters\0".to_vec();
+    text.extend(PARAMETERS.as_bytes());
+    fs::write(&generated, png(&[(b"tEXt", text)])).unwrap();
+    let generation = Generation::read(&generated).unwrap().unwrap();
+    assert_eq!(generation.seed.as_deref(), Some("1234"));
+
+    let comfy = root.path().join("comfy.png");
+    let mut text = b"prompt\0\0\0\0\0".to_vec();
+    text.extend(r#"{"1": {"inputs": {"positive": "a fox", "negative": "bad"}}}"#.as_bytes());
+    fs::write(&comfy, png(&[(b"iTXt", text)])).unwrap();
+    let generation = Generation::read(&comfy).unwrap().unwrap();
+    assert_eq!(generation.prompt, "a fox");
+
+    let plain = root.path().join("plain.png");
+    fs::write(&plain, png(&[])).unwrap();
+    assert_eq!(Generation::read(&plain).unwrap(), None);
+
+    // A chunk claiming more than the file holds
+    let truncated = root.path().join("truncated.png");
+    let mut contents = png(&[]);
+    contents.truncate(8);
+    contents.extend(u32::MAX.to_be_bytes());
+    contents.extend(b"tEXtparameters\0");
+    fs::write(&truncated, contents).unwrap();
+    assert!(Generation::read(&truncated).is_err());
+}
+
+#[test]
+fn prompt_syntax_is_stripped() {
+    assert_eq!(
+        strip_prompt_syntax("masterpiece, (a dog:1.2), <lora:dogs:0.8>, [blurry], ((sharp))"),
+        "masterpiece, a dog, blurry, sharp"
+    );
+    assert_eq!(
+        strip_prompt_syntax("<lyco:x:1>  a  photo (by \\(artist\\):0.9)"),
+        "a photo by (artist)"
+    );
+    assert_eq!(strip_prompt_syntax("time: 12:30"), "time: 12:30");
+}
@@ -0,0 +1,127 @@
+use std::fs;
+
+use quicklabel::{Class, Dataset, Problem, journal::INTENT_LOG_NAME};
+use tempfile::TempDir;
+
+/// Output root with `files`, given relative to it
+fn setup(files: &[(&str, &str)]) -> (TempDir, Dataset) {
+    let root = tempfile::tempdir().unwrap();
+
+    for (path, contents) in files {
+        let path = root.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let dataset = Dataset::new(root.path().join("input"), root.path());
+    (root, dataset)
+}
+
+#[test]
+fn class_dir_names_are_parsed() {
+    assert_eq!(Class::from_dir_name("10_dog"), Some(Class::new("dog", 10)));
+    assert_eq!(
+        Class::from_dir_name("3_black_cat"),
+        Some(Class::new("black_cat", 3))
+    );
+    assert_eq!(Class::from_dir_name("dog"), None);
+    assert_eq!(Class::from_dir_name("0_dog"), None);
+    assert_eq!(Class::from_dir_name("10_"), None);
+    assert_eq!(Class::from_dir_name("x_dog"), None);
+}
+
+#[test]
+fn scan_finds_class_directories() {
+    let (root, mut dataset) = setup(&[
+        ("10_dog/a.png", ""),
+        ("10_dog/a.txt", "dog"),
+        ("10_dog/b.jpg", ""),
+        ("3_cat/c.png", ""),
+        ("trash/d.png", ""),
+        ("misc/e.png", ""),
+        (".cache/f.png", ""),
+    ]);
+    dataset.trash_dir = Some(root.path().join("trash"));
+
+    let scan = dataset.scan().unwrap();
+    let classes: Vec<_> = scan.classes.iter().map(|dir| dir.class.clone()).collect();
+
+    assert_eq!(classes, [Class::new("dog", 10), Class::new("cat", 3)]);
+    assert_eq!(dataset.existing_classes().unwrap(), classes);
+    assert_eq!(scan.classes[0].images.len(), 2);
+    assert_eq!(scan.classes[0].captioned(), 1);
+    assert_eq!(scan.images(), 3);
+    assert_eq!(scan.steps(), 23);
+    assert_eq!(scan.unrecognized, [root.path().join("misc")]);
+}
+
+#[test]
+fn validate_reports_problems() {
+    let (root, dataset) = setup(&[
+        ("10_dog/a.png", ""),
+        ("10_dog/a.txt", "dog"),
+        ("10_dog/b.png", ""),
+        ("10_dog/c.png", ""),
+        ("10_dog/c.txt", " \n"),
+        ("10_dog/gone.txt", "dog"),
+        ("2_dog/d.png", ""),
+        ("2_dog/d.txt", "dog"),
+        (INTENT_LOG_NAME, ""),
+    ]);
+    let path = |path: &str| root.path().join(path);
+
+    assert_eq!(
+        dataset.validate().unwrap(),
+        [
+            Problem::Interrupted(path(INTENT_LOG_NAME)),
+            Problem::MissingCaption(path("10_dog/b.txt")),
+            Problem::EmptyCaption(path("10_dog/c.txt")),
+            Problem::OrphanedCaption(path("10_dog/gone.txt")),
+            Problem::DuplicateLabel("dog".into()),
+        ]
+    );
+}
+
+#[test]
+fn validate_reports_missing_triggers() {
+    let (root, mut dataset) = setup(&[
+        ("10_dog/a.png", ""),
+        ("10_dog/a.txt", "ohwx dog, running"),
+        ("10_dog/b.png", ""),
+        ("10_dog/b.txt", "a dog"),
+    ]);
+    dataset
+        .classes
+        .push(Class::new("dog", 10).with_trigger("ohwx dog"));
+
+    assert_eq!(
+        dataset.validate().unwrap(),
+        [Problem::MissingTrigger(
+            root.path().join("10_dog/b.txt"),
+            "ohwx dog".into()
+        )]
+    );
+}
+
+#[test]
+fn validate_reports_truncated_captions() {
+    let long = "a dog, ".repeat(30);
+    let (root, mut dataset) = setup(&[
+        ("10_dog/a.png", ""),
+        ("10_dog/a.txt", &long),
+        ("10_dog/b.png", ""),
+        ("10_dog/b.txt", "a dog\non a beach"),
+    ]);
+
+    assert_eq!(
+        dataset.validate().unwrap(),
+        [Problem::TruncatedCaption(
+            root.path().join("10_dog/a.txt"),
+            90,
+            75
+        )]
+    );
+
+    dataset.max_token_length = 150;
+    assert_eq!(dataset.validate().unwrap(), []);
+}
@@ -0,0 +1,105 @@
+use std::fs;
+
+use quicklabel::{
+    Class, Dataset,
+    tags::{self, TagDictionary, TagStyle},
+};
+
+const TAG_LIST: &str = r#"name,category,post_count,aliases
+1girl,0,4114588,"1girls,sole_female"
+long_hair,0,3392156,"/lh,longhair"
+long_sleeves,0,1200000,
+hatsune_miku,4,95000,"miku,初音ミク"
+^_^,0,20000,
+"#;
+
+#[test]
+fn tags_are_normalized() {
+    assert_eq!(
+        tags::normalize(" long_hair ", TagStyle::Spaces),
+        "long hair"
+    );
+    assert_eq!(
+        tags::normalize("long   hair", TagStyle::Underscores),
+        "long_hair"
+    );
+    // Kaomoji are drawn with their underscores
+    assert_eq!(tags::normalize("^_^", TagStyle::Spaces), "^_^");
+
+    assert_eq!(
+        tags::split("1girl, long_hair,,\nsmile "),
+        ["1girl", "long_hair", "smile"]
+    );
+    assert_eq!(
+        tags::normalize_caption("1girl, long_hair, long hair,\nsmile", TagStyle::Spaces),
+        "1girl, long hair, smile"
+    );
+}
+
+#[test]
+fn tag_lists_are_parsed() {
+    let dictionary = TagDictionary::parse_csv(TAG_LIST);
+
+    // The header isn't a tag
+    assert_eq!(dictionary.len(), 5);
+    assert_eq!(dictionary.canonical("Long Hair"), Some("long_hair"));
+    assert_eq!(dictionary.canonical("sole female"), Some("1girl"));
+    assert_eq!(dictionary.canonical("miku"), Some("hatsune_miku"));
+    assert_eq!(dictionary.canonical("short hair"), None);
+
+    let counts_only = TagDictionary::parse_csv("smile,12\nblush,8");
+    assert_eq!(counts_only.len(), 2);
+    assert_eq!(counts_only.suggest("sm", 1)[0].tag.post_count, 12);
+
+    // An empty count isn't the category
+    let uncounted = TagDictionary::parse_csv("smile,4,,\"grin\"\nblush,4,8,");
+    assert_eq!(uncounted.len(), 2);
+    assert_eq!(uncounted.suggest("sm", 1)[0].tag.post_count, 0);
+    assert_eq!(uncounted.canonical("grin"), Some("smile"));
+    assert_eq!(uncounted.suggest("bl", 1)[0].tag.post_count, 8);
+}
+
+#[test]
+fn suggestions_prefer_the_dataset_then_popularity() {
+    fn names<'a>(dictionary: &'a TagDictionary, typed: &str) -> Vec<(&'a str, Option<&'a str>)> {
+        dictionary
+            .suggest(typed, 10)
+            .into_iter()
+            .map(|suggestion| (suggestion.tag.name.as_str(), suggestion.alias))
+            .collect()
+    }
+
+    let mut dictionary = TagDictionary::parse_csv(TAG_LIST);
+
+    assert_eq!(
+        names(&dictionary, "long "),
+        [("long_hair", None), ("long_sleeves", None)]
+    );
+    assert_eq!(names(&dictionary, "MIK"), [("hatsune_miku", Some("miku"))]);
+    assert!(names(&dictionary, "").is_empty());
+
+    dictionary.add_used(["long sleeves", "long sleeves", "long braid"]);
+    assert_eq!(
+        names(&dictionary, "long_"),
+        [
+            ("long_sleeves", None),
+            ("long braid", None),
+            ("long_hair", None)
+        ]
+    );
+}
+
+#[test]
+fn used_tags_come_from_the_captions() {
+    let root = tempfile::tempdir().unwrap();
+    let mut dataset = Dataset::new(root.path().join("input"), root.path().join("output"));
+    dataset.classes.push(Class::new("miku", 10));
+    dataset.prepare().unwrap();
+
+    let class_dir = root.path().join("output/10_miku");
+    fs::write(class_dir.join("a.png"), "").unwrap();
+    fs::write(class_dir.join("a.txt"), "1girl, long hair").unwrap();
+    fs::write(class_dir.join("b.png"), "").unwrap();
+
+    assert_eq!(dataset.used_tags().unwrap(), ["1girl", "long hair"]);
+}
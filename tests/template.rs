@@ -0,0 +1,88 @@
+use std::{fs, path::Path};
+
+use quicklabel::{Template, TemplateError};
+
+fn render(source: &str, pattern: Option<&str>, image: &Path) -> String {
+    Template::parse(source, pattern)
+        .unwrap()
+        .render(image)
+        .unwrap()
+}
+
+/// Just enough of a PNG for its size to be read
+fn png_header(width: u32, height: u32) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend(width.to_be_bytes());
+    png.extend(height.to_be_bytes());
+    png.extend([8, 6, 0, 0, 0]);
+    png
+}
+
+#[test]
+fn file_placeholders_are_rendered() {
+    let image = Path::new("photos/beach/IMG_0042.jpg");
+
+    assert_eq!(
+        render("{stem} from {parent_dir} ({filename})", None, image),
+        "IMG_0042 from beach (IMG_0042.jpg)"
+    );
+    assert_eq!(render("{{stem}}", None, image), "{stem}");
+}
+
+#[test]
+fn class_placeholders_are_kept_for_later() {
+    let image = Path::new("a.png");
+
+    assert_eq!(
+        render("{trigger}, a {class}", None, image),
+        "{trigger}, a {class}"
+    );
+}
+
+#[test]
+fn filename_captures_are_rendered() {
+    let pattern = Some(r"^(?<subject>[a-z]+)_(\d+)");
+    let image = Path::new("dog_12.png");
+
+    assert_eq!(render("{subject} #{2}", pattern, image), "dog #12");
+    assert_eq!(render("{subject}", pattern, Path::new("x.png")), "");
+}
+
+#[test]
+fn size_is_read_from_the_image() {
+    let root = tempfile::tempdir().unwrap();
+    let image = root.path().join("a.png");
+    fs::write(&image, png_header(640, 480)).unwrap();
+
+    assert_eq!(render("{width}x{height}", None, &image), "640x480");
+    assert_eq!(render("{exif.DateTimeOriginal}", None, &image), "");
+
+    let missing = root.path().join("missing.png");
+    assert!(
+        Template::parse("{width}", None)
+            .unwrap()
+            .render(&missing)
+            .is_err()
+    );
+}
+
+#[test]
+fn invalid_templates_are_rejected() {
+    let parse = |source: &str, pattern: Option<&str>| Template::parse(source, pattern).err();
+
+    assert_eq!(
+        parse("{name}", None),
+        Some(TemplateError::UnknownPlaceholder("name".into()))
+    );
+    assert_eq!(
+        parse("{3}", Some("(a)(b)")),
+        Some(TemplateError::UnknownPlaceholder("3".into()))
+    );
+    assert_eq!(parse("{stem", None), Some(TemplateError::Unclosed));
+    assert_eq!(parse("stem}", None), Some(TemplateError::Unopened));
+    assert!(matches!(
+        parse("", Some("(")),
+        Some(TemplateError::InvalidPattern(..))
+    ));
+    assert_eq!(parse("{exif.Make} {2}", Some("(a)(b)")), None);
+}
@@ -0,0 +1,20 @@
+use quicklabel::tokens;
+
+#[test]
+fn tokens_are_counted_per_line() {
+    assert_eq!(tokens::count(""), 0);
+    assert_eq!(tokens::count("a photo of a dog"), 5);
+    assert_eq!(tokens::count("A Photo, of a DOG!"), 7);
+    assert_eq!(tokens::longest_line("a dog\na photo of a dog\n"), 5);
+}
+
+#[test]
+fn truncation_starts_after_the_last_kept_token() {
+    let line = "a dog, ".repeat(30);
+
+    assert_eq!(tokens::truncation(&line, 225), None);
+    // Each "a dog, " is three tokens
+    assert_eq!(tokens::truncation(&line, 75), Some(25 * 7));
+    assert_eq!(tokens::truncation(&line, 76), Some(25 * 7 + 2));
+    assert_eq!(tokens::truncation("a dog", 5), None);
+}
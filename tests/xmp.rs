@@ -0,0 +1,264 @@
+use std::fs;
+
+use quicklabel::{
+    Class, Dataset,
+    journal::INTENT_LOG_NAME,
+    xmp::{self, XmpCaption, XmpSink},
+};
+
+/// A PNG with an IHDR chunk and (empty) image data
+fn png() -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut ihdr = 1u32.to_be_bytes().repeat(2);
+    ihdr.extend([8, 6, 0, 0, 0]);
+    for (kind, data) in [
+        (b"IHDR", ihdr),
+        (b"IDAT", Vec::new()),
+        (b"IEND", Vec::new()),
+    ] {
+        png.extend((data.len() as u32).to_be_bytes());
+        png.extend(kind);
+        png.extend(data);
+        png.extend([0; 4]);
+    }
+    png
+}
+
+/// A 1x1 JPEG with a JFIF segment, followed by stand-in image data
+fn jpeg() -> Vec<u8> {
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 16];
+    jpeg.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    jpeg.extend([0xff, 0xc0, 0, 11, 8, 0, 1, 0, 1, 1, 1, 0x11, 0]);
+    jpeg.extend([0xff, 0xda, 0, 2, 0x12, 0x34, 0xff, 0xd9]);
+    jpeg
+}
+
+/// A JPEG cut off in its first segment, which only starts like one
+fn truncated_jpeg() -> Vec<u8> {
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 64];
+    jpeg.extend(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0\xff\xc0\0\x0b\x08\0");
+    jpeg
+}
+
+/// A lossless 1x1 WebP with stand-in image data
+fn webp() -> Vec<u8> {
+    let vp8l = [0x2f, 0, 0, 0, 0x10, 0];
+    let mut webp = b"RIFF".to_vec();
+    webp.extend((4 + 8 + vp8l.len() as u32).to_le_bytes());
+    webp.extend(b"WEBPVP8L");
+    webp.extend(5u32.to_le_bytes());
+    webp.extend(vp8l);
+    webp
+}
+
+#[test]
+fn packets_round_trip() {
+    let caption = XmpCaption {
+        description: Some("a dog & a <cat>".into()),
+        subjects: vec!["dog".into(), "\"pets\"".into()],
+    };
+    let parsed = XmpCaption::parse(&caption.packet());
+
+    assert_eq!(parsed, caption);
+    assert_eq!(parsed.caption().as_deref(), Some("a dog & a <cat>"));
+
+    let keywords = XmpCaption {
+        description: None,
+        subjects: vec!["dog".into(), "beach".into()],
+    };
+    assert_eq!(keywords.caption().as_deref(), Some("dog, beach"));
+    assert_eq!(XmpCaption::default().caption(), None);
+}
+
+#[test]
+fn packets_are_embedded_and_removed() {
+    let root = tempfile::tempdir().unwrap();
+    let packet = XmpCaption {
+        description: Some("a dog".into()),
+        subjects: vec!["dog".into()],
+    }
+    .packet();
+
+    for (name, bytes) in [("a.png", png()), ("a.jpg", jpeg()), ("a.webp", webp())] {
+        let image = root.path().join(name);
+        fs::write(&image, &bytes).unwrap();
+        assert!(xmp::can_embed(&image));
+        assert_eq!(xmp::read_embedded(&image).unwrap(), None);
+
+        xmp::write_embedded(&image, Some(&packet)).unwrap();
+        assert_eq!(xmp::read_embedded(&image).unwrap(), Some(packet.clone()));
+        assert_eq!(
+            xmp::read(&image).unwrap().unwrap().description.as_deref(),
+            Some("a dog")
+        );
+        // The image is still readable
+        assert_eq!(imagesize::size(&image).unwrap().width, 1, "{name}");
+
+        xmp::write_embedded(&image, None).unwrap();
+        assert_eq!(xmp::read_embedded(&image).unwrap(), None);
+    }
+
+    let gif = root.path().join("a.gif");
+    fs::write(&gif, b"GIF89a").unwrap();
+    assert!(!xmp::can_embed(&gif));
+    assert!(xmp::write_embedded(&gif, Some(&packet)).is_err());
+}
+
+#[test]
+fn malformed_images_are_refused() {
+    let root = tempfile::tempdir().unwrap();
+    let packet = XmpCaption::default().packet();
+
+    // A segment too short to say what it is, which is kept as it is
+    let jpeg_path = root.path().join("short.jpg");
+    let mut bytes = vec![0xff, 0xd8, 0xff, 0xe1, 0, 2];
+    bytes.extend(&jpeg()[2..]);
+    fs::write(&jpeg_path, bytes).unwrap();
+    xmp::write_embedded(&jpeg_path, Some(&packet)).unwrap();
+    assert_eq!(
+        xmp::read_embedded(&jpeg_path).unwrap(),
+        Some(packet.clone())
+    );
+
+    let webp_path = root.path().join("empty.webp");
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend(12u32.to_le_bytes());
+    bytes.extend(b"WEBPVP8X");
+    bytes.extend(0u32.to_le_bytes());
+    fs::write(&webp_path, bytes).unwrap();
+    assert!(xmp::write_embedded(&webp_path, Some(&packet)).is_err());
+}
+
+#[test]
+fn sidecars_are_read_next_to_images() {
+    let root = tempfile::tempdir().unwrap();
+    let image = root.path().join("a.png");
+    fs::write(&image, png()).unwrap();
+    assert_eq!(xmp::read(&image).unwrap(), None);
+
+    let caption = XmpCaption {
+        description: Some("a dog".into()),
+        subjects: Vec::new(),
+    };
+    fs::write(image.with_extension("xmp"), caption.packet()).unwrap();
+    assert_eq!(xmp::read(&image).unwrap(), Some(caption));
+}
+
+#[test]
+fn labeling_writes_xmp_and_undo_removes_it() {
+    let root = tempfile::tempdir().unwrap();
+    let input = root.path().join("input");
+    let output = root.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    fs::write(input.join("a.png"), png()).unwrap();
+    fs::write(input.join("b.gif"), b"GIF89a").unwrap();
+
+    let mut dataset = Dataset::new(input.clone(), output.clone());
+    dataset.classes.push(Class::new("dog", 10));
+    dataset.prepare().unwrap();
+    let class_dir = output.join("10_dog");
+
+    dataset.xmp = XmpSink::Sidecar;
+    let entry = dataset
+        .label(&input.join("a.png"), &dataset.classes[0], "a dog")
+        .unwrap()
+        .unwrap();
+    let written = xmp::read(&class_dir.join("a.png")).unwrap().unwrap();
+    assert_eq!(written.description.as_deref(), Some("a dog"));
+    assert_eq!(written.subjects, ["dog"]);
+    assert!(class_dir.join("a.png.xmp").is_file());
+
+    dataset.undo(&entry).unwrap();
+    assert!(!class_dir.join("a.png.xmp").exists());
+
+    dataset.xmp = XmpSink::Embed;
+    let entry = dataset
+        .label(&input.join("a.png"), &dataset.classes[0], "a dog")
+        .unwrap()
+        .unwrap();
+    assert!(!class_dir.join("a.png.xmp").exists());
+    assert!(
+        xmp::read_embedded(&class_dir.join("a.png"))
+            .unwrap()
+            .is_some()
+    );
+
+    dataset.undo(&entry).unwrap();
+    assert_eq!(fs::read(input.join("a.png")).unwrap(), png());
+
+    // Images XMP can't be embedded in get a sidecar instead
+    dataset
+        .label(&input.join("b.gif"), &dataset.classes[0], "a dog")
+        .unwrap()
+        .unwrap();
+    assert!(class_dir.join("b.gif.xmp").is_file());
+}
+
+#[test]
+fn truncated_images_get_a_sidecar() {
+    let root = tempfile::tempdir().unwrap();
+    let input = root.path().join("input");
+    let output = root.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    fs::write(input.join("a.jpg"), truncated_jpeg()).unwrap();
+    assert!(!xmp::can_embed(&input.join("a.jpg")));
+
+    let mut dataset = Dataset::new(input.clone(), output.clone());
+    dataset.classes.push(Class::new("dog", 10));
+    dataset.xmp = XmpSink::Embed;
+    dataset.prepare().unwrap();
+
+    dataset
+        .label(&input.join("a.jpg"), &dataset.classes[0], "a dog")
+        .unwrap()
+        .unwrap();
+
+    let class_dir = output.join("10_dog");
+    assert_eq!(fs::read(class_dir.join("a.jpg")).unwrap(), truncated_jpeg());
+    assert!(class_dir.join("a.jpg.xmp").is_file());
+    assert!(!output.join(INTENT_LOG_NAME).exists());
+}
+
+#[test]
+fn recovery_finishes_embeds_that_cant_succeed() {
+    let root = tempfile::tempdir().unwrap();
+    let input = root.path().join("input");
+    let output = root.path().join("output");
+    fs::create_dir_all(&input).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    let image = input.join("a.jpg");
+    fs::write(&image, jpeg()).unwrap();
+
+    let mut dataset = Dataset::new(input.clone(), output.clone());
+    dataset.classes.push(Class::new("dog", 10));
+    dataset.xmp = XmpSink::Embed;
+    dataset.prepare().unwrap();
+
+    let entry = dataset
+        .plan_label(&image, &dataset.classes[0], "a dog")
+        .resolve(dataset.collision_policy)
+        .unwrap()
+        .unwrap();
+    assert!(entry.xmp.as_ref().unwrap().embedded);
+
+    // Interrupted after the transfer, with the image truncated since it was planned
+    let mut intent = toml::Table::new();
+    intent.insert("entry".into(), toml::Value::try_from(&entry).unwrap());
+    intent.insert("revert".into(), false.into());
+    fs::write(
+        output.join(INTENT_LOG_NAME),
+        toml::to_string(&intent).unwrap(),
+    )
+    .unwrap();
+    let destination = entry.destination.clone().unwrap();
+    fs::remove_file(&image).unwrap();
+    fs::write(&destination, truncated_jpeg()).unwrap();
+
+    dataset.prepare().unwrap();
+
+    assert_eq!(fs::read(&destination).unwrap(), truncated_jpeg());
+    assert!(destination.with_extension("txt").is_file());
+    assert!(!output.join(INTENT_LOG_NAME).exists());
+}